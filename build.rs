@@ -0,0 +1,26 @@
+// build the readline shim that rewrites `... | mp` in bash. it gets
+// embedded in mp, which writes it out for --print-preload-path, so that
+// `cargo install` gets it too
+
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/preload.c");
+
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("magic_pager.so");
+
+    // cc only builds static libraries, so run the compiler it finds ourselves
+    let status = cc::Build::new()
+        .get_compiler()
+        .to_command()
+        .args(["-shared", "-fPIC", "-Wall", "-o"])
+        .arg(&out)
+        .arg("src/preload.c")
+        .arg("-ldl")
+        .status()
+        .expect("failed to run the c compiler");
+
+    if !status.success() {
+        panic!("failed to build src/preload.c");
+    }
+}
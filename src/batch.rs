@@ -0,0 +1,51 @@
+// helpers for batch mode, where each refresh is printed to stdout instead of
+// being drawn on the terminal
+
+use std::process::ExitStatus;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the current time in utc as eg. 2024-01-31 13:45:00
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    // days since the epoch to a civil date, from Howard Hinnant's algorithm
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let rem = secs % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+// how a command finished, for the separator line
+pub fn describe(status: Option<ExitStatus>) -> String {
+    use std::os::unix::process::ExitStatusExt;
+
+    match status {
+        Some(s) => match (s.code(), s.signal()) {
+            (Some(c), _) => format!("exit {c}"),
+            (_, Some(sig)) => format!("signal {sig}"),
+            _ => "exit ?".into(),
+        },
+        None => "read".into(),
+    }
+}
+
+// the line printed before each refresh
+pub fn separator(title: &str, status: &str) -> String {
+    format!("--- {} {} ({}) ---", timestamp(), title, status)
+}
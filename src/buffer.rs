@@ -0,0 +1,285 @@
+// storage for the lines of output.
+//
+// everything lives in one contiguous block of bytes, an arena that command
+// output or the file being paged is copied into, with the offsets of each
+// line kept alongside. a file read in whole is scanned for newlines as lines
+// are asked for. drawing needs the number of lines, so in practice a file is
+// scanned once each time it is read, and only keeping the head with
+// --max-lines stops short of the end.
+//
+// files aren't memory mapped, since a mapped file that is truncated kills
+// the process with SIGBUS as soon as a line past the new end is looked at,
+// and logs being watched get truncated all the time.
+
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+// which end of the output to keep when the number of lines is capped
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    Head,
+    Tail,
+}
+
+pub struct Buffer {
+    data: Vec<u8>,
+    // start and end of each line in data, without the newline
+    lines: RefCell<Vec<(usize, usize)>>,
+    // how far through data we have looked for newlines
+    indexed: Cell<usize>,
+    // lines at the front of `lines` that have been dropped
+    first: Cell<usize>,
+    max: Option<(usize, Keep)>,
+}
+
+impl Buffer {
+    pub fn new(max: Option<(usize, Keep)>) -> Buffer {
+        Buffer {
+            data: vec![],
+            lines: RefCell::new(vec![]),
+            indexed: Cell::new(0),
+            first: Cell::new(0),
+            max,
+        }
+    }
+
+    // read a file in. when the lines are capped they are pushed as they are
+    // read, so that keeping the head stops early and keeping the tail doesn't
+    // hold the whole file, otherwise lines are indexed as they are needed
+    pub fn read(path: &Path, max: Option<(usize, Keep)>) -> std::io::Result<Buffer> {
+        if max.is_none() {
+            return Ok(Buffer {
+                data: std::fs::read(path)?,
+                ..Buffer::new(max)
+            });
+        }
+
+        let mut buf = Buffer::new(max);
+        let mut f = BufReader::new(File::open(path)?);
+        let mut line = vec![];
+        while f.read_until(b'\n', &mut line)? > 0 {
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            if !buf.push(&line) {
+                break;
+            }
+            line.clear();
+        }
+        Ok(buf)
+    }
+
+    // drop all the lines, keeping the allocation for reuse
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.lines.get_mut().clear();
+        self.indexed.set(0);
+        self.first.set(0);
+    }
+
+    // append a line to the arena. returns false if the buffer is full and
+    // no more lines will be kept
+    pub fn push(&mut self, line: &[u8]) -> bool {
+        let data = &mut self.data;
+        let lines = self.lines.get_mut();
+        let first = self.first.get_mut();
+
+        if let Some((max, keep)) = self.max {
+            if lines.len() - *first >= max {
+                match keep {
+                    Keep::Head => return false,
+                    Keep::Tail => *first += 1,
+                }
+            }
+
+            // once as much has been dropped as is kept, shift the arena down
+            if *first > 0 && *first >= max {
+                let start = lines.get(*first).map_or(data.len(), |l| l.0);
+                data.drain(..start);
+                lines.drain(..*first);
+                for (s, e) in lines.iter_mut() {
+                    *s -= start;
+                    *e -= start;
+                }
+                *first = 0;
+            }
+        }
+
+        let start = data.len();
+        data.extend_from_slice(line);
+        data.push(b'\n');
+        lines.push((start, start + line.len()));
+        self.indexed.set(data.len());
+
+        true
+    }
+
+    // remove the last line from the arena
+    pub fn pop(&mut self) {
+        let data = &mut self.data;
+        let lines = self.lines.get_mut();
+        if lines.len() > self.first.get() {
+            let (start, _) = lines.pop().unwrap();
+            data.truncate(start);
+            self.indexed.set(start);
+        }
+    }
+
+    // look for newlines until n lines are known or the data runs out. only
+    // a file read in whole has lines left to find, capped lines are all
+    // pushed
+    fn index(&self, n: usize) {
+        let data = &self.data;
+        let mut lines = self.lines.borrow_mut();
+        let mut pos = self.indexed.get();
+
+        while lines.len() < n && pos < data.len() {
+            let end = data[pos..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map(|i| pos + i)
+                .unwrap_or(data.len());
+
+            lines.push((pos, end));
+            pos = end + 1;
+        }
+        self.indexed.set(pos.min(data.len()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.index(usize::MAX);
+        self.lines.borrow().len() - self.first.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index(self.first.get() + 1);
+        self.lines.borrow().len() == self.first.get()
+    }
+
+    pub fn line(&self, i: usize) -> &[u8] {
+        self.index(self.first.get() + i + 1);
+        let i = i + self.first.get();
+        let (start, end) = self.lines.borrow()[i];
+        &self.data[start..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(buf: &Buffer) -> Vec<&[u8]> {
+        (0..buf.len()).map(|i| buf.line(i)).collect()
+    }
+
+    #[test]
+    fn push() {
+        let mut buf = Buffer::new(None);
+        assert!(buf.is_empty());
+        assert!(buf.push(b"one"));
+        assert!(buf.push(b""));
+        assert!(buf.push(b"three"));
+        assert_eq!(lines(&buf), [&b"one"[..], b"", b"three"]);
+    }
+
+    #[test]
+    fn pop() {
+        let mut buf = Buffer::new(None);
+        buf.push(b"one");
+        buf.push(b"two");
+        buf.pop();
+        assert_eq!(lines(&buf), [&b"one"[..]]);
+
+        // the arena is reused by the next line
+        buf.push(b"three");
+        assert_eq!(lines(&buf), [&b"one"[..], b"three"]);
+
+        buf.pop();
+        buf.pop();
+        buf.pop();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn cap_head() {
+        let mut buf = Buffer::new(Some((2, Keep::Head)));
+        assert!(buf.push(b"one"));
+        assert!(buf.push(b"two"));
+        assert!(!buf.push(b"three"));
+        assert_eq!(lines(&buf), [&b"one"[..], b"two"]);
+    }
+
+    #[test]
+    fn cap_tail() {
+        let mut buf = Buffer::new(Some((2, Keep::Tail)));
+        for i in 0..7 {
+            assert!(buf.push(i.to_string().as_bytes()));
+            assert_eq!(buf.len(), (i + 1).min(2));
+        }
+        assert_eq!(lines(&buf), [&b"5"[..], b"6"]);
+
+        // popping doesn't bring back lines that were dropped
+        buf.pop();
+        buf.pop();
+        buf.pop();
+        assert!(buf.is_empty());
+        buf.push(b"7");
+        assert_eq!(lines(&buf), [&b"7"[..]]);
+    }
+
+    #[test]
+    fn cap_one() {
+        let mut buf = Buffer::new(Some((1, Keep::Tail)));
+        buf.push(b"one");
+        buf.push(b"two");
+        buf.push(b"three");
+        assert_eq!(lines(&buf), [&b"three"[..]]);
+    }
+
+    #[test]
+    fn clear() {
+        let mut buf = Buffer::new(Some((2, Keep::Tail)));
+        buf.push(b"one");
+        buf.push(b"two");
+        buf.push(b"three");
+        buf.clear();
+        assert!(buf.is_empty());
+        buf.push(b"four");
+        assert_eq!(lines(&buf), [&b"four"[..]]);
+    }
+
+    #[test]
+    fn read() {
+        let path = std::env::temp_dir().join(format!("mp-buffer-{}", std::process::id()));
+        std::fs::write(&path, b"one\ntwo\nthree\nfour").unwrap();
+
+        let buf = Buffer::read(&path, None).unwrap();
+        assert_eq!(lines(&buf), [&b"one"[..], b"two", b"three", b"four"]);
+
+        let buf = Buffer::read(&path, Some((2, Keep::Head))).unwrap();
+        assert_eq!(lines(&buf), [&b"one"[..], b"two"]);
+
+        let buf = Buffer::read(&path, Some((2, Keep::Tail))).unwrap();
+        assert_eq!(lines(&buf), [&b"three"[..], b"four"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated() {
+        let path = std::env::temp_dir().join(format!("mp-truncated-{}", std::process::id()));
+        let text = (0..2000).map(|i| format!("{i}\n")).collect::<String>();
+        std::fs::write(&path, text).unwrap();
+
+        // the lines are looked for after the file shrinks, which would be
+        // past the end of a mapping
+        let buf = Buffer::read(&path, None).unwrap();
+        std::fs::write(&path, b"short\n").unwrap();
+        assert_eq!(buf.len(), 2000);
+        assert_eq!(buf.line(1500), b"1500");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+// working out which lines changed between two versions of the output
+
+// the lines that were removed and added, prefixed with '-' and '+' like a
+// diff without the context
+pub fn lines(old: &[String], new: &[String]) -> String {
+    let (pre, suf) = trim(old.len(), new.len(), |i, j| old[i] == new[j]);
+    let old = &old[pre..old.len() - suf];
+    let new = &new[pre..new.len() - suf];
+
+    let mut out = String::new();
+    let mut push = |sign: char, line: &str| {
+        out.push(sign);
+        out.push_str(line);
+        out.push('\n');
+    };
+
+    // everything between two lines that stayed is what changed
+    let (mut i, mut j) = (0, 0);
+    for (a, b) in matches(old.len(), new.len(), |i, j| old[i] == new[j])
+        .into_iter()
+        .chain([(old.len(), new.len())])
+    {
+        old[i..a].iter().for_each(|l| push('-', l));
+        new[j..b].iter().for_each(|l| push('+', l));
+        (i, j) = (a + 1, b + 1);
+    }
+
+    out
+}
+
+// where a line of the old version ended up in the new one. a line that
+// changed goes to the same place in whatever replaced it. the lines are
+// compared by where they are in each, so they never need copying out
+pub fn follow(old: usize, new: usize, line: usize, same: impl Fn(usize, usize) -> bool) -> usize {
+    // the line after one that was removed from the end isn't there
+    place(old, new, line, same).min(new.saturating_sub(1))
+}
+
+fn place(old: usize, new: usize, line: usize, same: impl Fn(usize, usize) -> bool) -> usize {
+    let (pre, suf) = trim(old, new, &same);
+    if line < pre {
+        return line;
+    }
+    if line >= old - suf {
+        return line + new - old;
+    }
+
+    let line = line - pre;
+    let (old, new) = (old - pre - suf, new - pre - suf);
+
+    let (mut i, mut j) = (0, 0);
+    for (a, b) in matches(old, new, |i, j| same(pre + i, pre + j))
+        .into_iter()
+        .chain([(old, new)])
+    {
+        if line == a {
+            return pre + b;
+        }
+        if line < a {
+            // nothing replaced it, so the line after takes its place
+            return pre + (j + line - i).min(b.saturating_sub(1).max(j));
+        }
+        (i, j) = (a + 1, b + 1);
+    }
+
+    pre + new
+}
+
+// how many lines at the start and end stayed the same
+fn trim(old: usize, new: usize, same: impl Fn(usize, usize) -> bool) -> (usize, usize) {
+    let pre = (0..old.min(new)).take_while(|&i| same(i, i)).count();
+    let suf = (0..old.min(new) - pre)
+        .take_while(|&k| same(old - 1 - k, new - 1 - k))
+        .count();
+    (pre, suf)
+}
+
+// the lines that are in both, as pairs of where they are in each
+fn matches(old: usize, new: usize, same: impl Fn(usize, usize) -> bool) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+
+    // too big to line up, just say everything changed
+    if old * new > 1 << 22 {
+        return pairs;
+    }
+
+    // longest common subsequence, from the end so that it can be walked
+    // forwards
+    let w = new + 1;
+    let mut lcs = vec![0u32; (old + 1) * w];
+    for i in (0..old).rev() {
+        for j in (0..new).rev() {
+            lcs[i * w + j] = if same(i, j) {
+                lcs[(i + 1) * w + j + 1] + 1
+            } else {
+                lcs[(i + 1) * w + j].max(lcs[i * w + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old && j < new {
+        if same(i, j) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * w + j] >= lcs[i * w + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn follows(old: &[&str], new: &[&str], line: usize) -> usize {
+        follow(old.len(), new.len(), line, |i, j| old[i] == new[j])
+    }
+
+    #[test]
+    fn changes() {
+        let old = ["a", "b", "c"].map(String::from);
+        let new = ["a", "B", "c", "d"].map(String::from);
+        assert_eq!(lines(&old, &new), "-b\n+B\n+d\n");
+        assert_eq!(lines(&old, &old), "");
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(follows(&["a", "b", "c"], &["a", "b", "c"], 1), 1);
+    }
+
+    #[test]
+    fn added() {
+        // above moves it down, below leaves it
+        assert_eq!(follows(&["a", "b", "c"], &["x", "y", "a", "b", "c"], 1), 3);
+        assert_eq!(follows(&["a", "b", "c"], &["a", "b", "x", "c"], 1), 1);
+        assert_eq!(follows(&["a", "b", "c"], &["a", "b", "c", "x"], 2), 2);
+        // in between lines that moved around
+        assert_eq!(follows(&["a", "b", "c", "d"], &["b", "x", "c", "d"], 2), 2);
+    }
+
+    #[test]
+    fn removed() {
+        // above moves it up
+        assert_eq!(follows(&["a", "b", "c"], &["b", "c"], 2), 1);
+        // the line itself goes to the one after
+        assert_eq!(follows(&["a", "b", "c"], &["a", "c"], 1), 1);
+        // or the one before, at the end
+        assert_eq!(follows(&["a", "b", "c"], &["a", "b"], 2), 1);
+        assert_eq!(follows(&["a", "b", "c"], &["a"], 2), 0);
+    }
+
+    #[test]
+    fn changed() {
+        assert_eq!(follows(&["a", "b", "c"], &["a", "B", "c"], 1), 1);
+        // replaced by more lines, it goes to the first of them
+        assert_eq!(follows(&["a", "b", "c"], &["a", "x", "y", "c"], 1), 1);
+        // the second of two lines replaced by one goes to it as well
+        assert_eq!(follows(&["a", "b", "c", "d"], &["a", "X", "d"], 2), 1);
+    }
+
+    #[test]
+    fn emptied() {
+        assert_eq!(follows(&["a", "b"], &[], 1), 0);
+    }
+
+    #[test]
+    fn too_big() {
+        // nothing at either end matches, so the middle would be lined up
+        let old = (0..3000).map(|i| i.to_string()).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new.insert(0, "x".into());
+        new[0] = "first".into();
+        *new.last_mut().unwrap() = "last".into();
+        let old = old.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        let new = new.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+
+        // past the cutoff it stays where it was rather than following the
+        // line that moved down
+        assert!(old.len() * new.len() > 1 << 22);
+        assert_eq!(follows(&old, &new, 1500), 1500);
+
+        // under it, the line is followed
+        let new = [&new[..1000], &["last"]].concat();
+        assert_eq!(follows(&old[..1000], &new, 500), 501);
+    }
+}
@@ -0,0 +1,47 @@
+// filters that hide lines which don't match a pattern, like `&` in less.
+// filters stack, so a line has to get through all of them to be shown.
+
+use regex::Regex;
+
+use crate::buffer::Buffer;
+use crate::line;
+
+pub struct Filter {
+    pub re: Regex,
+    // show the lines that don't match instead
+    pub invert: bool,
+}
+
+impl Filter {
+    // parse a pattern as typed after '&', a leading '!' inverts it
+    pub fn parse(pattern: &str) -> Result<Filter, regex::Error> {
+        let (invert, pattern) = match pattern.strip_prefix('!') {
+            Some(p) => (true, p),
+            None => (false, pattern),
+        };
+
+        Ok(Filter {
+            re: Regex::new(pattern)?,
+            invert,
+        })
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        self.re.is_match(text) != self.invert
+    }
+}
+
+// the indices of the lines in the buffer that get through every filter.
+// the first `keep` lines are a header and always get through
+pub fn apply(filters: &[Filter], buf: &Buffer, keep: usize) -> Vec<usize> {
+    (0..buf.len())
+        .filter(|i| {
+            if *i < keep {
+                return true;
+            }
+
+            let text = line::normalize(buf.line(*i)).text;
+            filters.iter().all(|f| f.matches(&text))
+        })
+        .collect()
+}
@@ -0,0 +1,31 @@
+// running commands given by the user after updates, eg. to log the output
+// or kick something off once tests start passing
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// run a command in the background with some input on stdin. its output
+// would mess up the screen, so it is only kept in batch mode
+pub fn run(cmd: &str, input: Vec<u8>, env: &[(&str, String)], quiet: bool) {
+    let mut c = Command::new("sh");
+    c.arg("-c").arg(cmd).stdin(Stdio::piped());
+    c.envs(env.iter().map(|(k, v)| (k, v)));
+    if quiet {
+        c.stdout(Stdio::null()).stderr(Stdio::null());
+    } else {
+        // stdout is the output of mp itself
+        c.stdout(std::io::stderr());
+    }
+
+    let Ok(mut child) = c.spawn() else {
+        return;
+    };
+
+    // feed it and wait for it on another thread so we don't hold anything up
+    std::thread::spawn(move || {
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(&input);
+        }
+        let _ = child.wait();
+    });
+}
@@ -0,0 +1,206 @@
+use std::io::Write;
+
+use crossterm::{cursor, style, terminal, QueueableCommand};
+
+use crate::ui::State;
+
+// the smallest a pane can be made, one line of output and the status bar
+const MIN_SIZE: u16 = 2;
+
+// several panes split across the terminal, either stacked on top of each
+// other or side by side
+pub struct Layout {
+    pub panes: Vec<State>,
+    pub focus: usize,
+    pub vsplit: bool,
+    // size of each pane along the direction of the split
+    sizes: Vec<u16>,
+    // set after ^W, the next key is a window command
+    window: bool,
+    // rows at the top of the terminal taken by the tab bar
+    pub top: u16,
+}
+
+impl Layout {
+    pub fn new(panes: Vec<State>) -> Layout {
+        Layout {
+            vsplit: panes[0].opts.vsplit,
+            panes,
+            focus: 0,
+            sizes: vec![],
+            window: false,
+            top: 0,
+        }
+    }
+
+    // how many panes there is room for at MIN_SIZE, the rest are hidden
+    fn shown(&self) -> usize {
+        let (w, h) = terminal::size().unwrap();
+        let room = if self.vsplit {
+            (w + 1) / (MIN_SIZE + 1)
+        } else {
+            h.saturating_sub(self.top) / MIN_SIZE
+        };
+        self.panes.len().min(room as usize)
+    }
+
+    // the space available to split between the panes that are shown
+    fn available(&self, shown: usize) -> u16 {
+        let (w, h) = terminal::size().unwrap();
+        if self.vsplit {
+            // a column between each pane for the separator
+            w.saturating_sub(shown.saturating_sub(1) as u16)
+        } else {
+            h.saturating_sub(self.top)
+        }
+    }
+
+    // make the sizes fill the terminal, keeping their proportions
+    fn fit(&mut self) {
+        let shown = self.shown();
+        let total = self.available(shown);
+
+        // start out equal, and again once panes have had to be hidden
+        let sum: u16 = self.sizes.iter().sum();
+        if self.sizes.len() != self.panes.len() || sum == 0 || self.sizes.contains(&0) {
+            self.sizes = vec![total / shown.max(1) as u16; self.panes.len()];
+        } else if sum != total {
+            self.sizes = self
+                .sizes
+                .iter()
+                .map(|s| (*s as u32 * total as u32 / sum as u32) as u16)
+                .collect();
+        }
+
+        self.sizes[shown..].fill(0);
+        if shown == 0 {
+            return;
+        }
+
+        // the last pane shown takes whatever is left over from rounding
+        let rest: u16 = self.sizes[..shown - 1].iter().sum();
+        self.sizes[shown - 1] = total.saturating_sub(rest);
+
+        // and none are smaller than MIN_SIZE, taking from the biggest
+        for i in 0..shown {
+            while self.sizes[i] < MIN_SIZE {
+                let big = (0..shown).max_by_key(|j| self.sizes[*j]).unwrap();
+                if self.sizes[big] <= MIN_SIZE {
+                    break;
+                }
+                self.sizes[big] -= 1;
+                self.sizes[i] += 1;
+            }
+        }
+    }
+
+    // give each pane its place on the terminal
+    pub fn resize(&mut self) {
+        self.fit();
+
+        let (w, h) = terminal::size().unwrap();
+        let h = h.saturating_sub(self.top);
+        let mut pos = 0;
+        for (i, p) in self.panes.iter_mut().enumerate() {
+            let size = self.sizes[i];
+            if self.vsplit {
+                p.origin = (pos, self.top);
+                p.size = (size, h);
+                if size > 0 {
+                    pos += size + 1;
+                }
+            } else {
+                p.origin = (0, self.top + pos);
+                p.size = (w, size);
+                pos += size;
+            }
+            p.focused = i == self.focus;
+            p.visible = size > 0;
+        }
+    }
+
+    // move a line/column from the neighbour of the focused pane into it, or
+    // the other way round when shrinking
+    fn grow(&mut self, shrink: bool) {
+        if self.panes.len() < 2 {
+            return;
+        }
+
+        let other = if self.focus + 1 < self.panes.len() {
+            self.focus + 1
+        } else {
+            self.focus - 1
+        };
+
+        let (from, to) = if shrink {
+            (self.focus, other)
+        } else {
+            (other, self.focus)
+        };
+
+        if self.sizes[from] > MIN_SIZE && self.sizes[to] > 0 {
+            self.sizes[from] -= 1;
+            self.sizes[to] += 1;
+        }
+    }
+
+    pub fn event(&mut self, c: char) {
+        if self.window {
+            self.window = false;
+            match c {
+                'w' | '\t' | '\x17' => self.focus = (self.focus + 1) % self.panes.len(),
+                'W' => self.focus = (self.focus + self.panes.len() - 1) % self.panes.len(),
+                '+' | '>' => self.grow(false),
+                '-' | '<' => self.grow(true),
+                '=' => self.sizes.clear(),
+                _ => {}
+            }
+            return;
+        }
+
+        if c == '\x17' && !self.typing() {
+            self.window = true;
+            return;
+        }
+
+        self.panes[self.focus].event(c);
+    }
+
+    pub fn typing(&self) -> bool {
+        self.panes[self.focus].typing()
+    }
+
+    pub fn draw(&mut self) {
+        self.resize();
+        let mut stdout = std::io::stdout();
+
+        // the focused pane goes last so that it places the cursor. panes
+        // there's no room for are left out
+        for (i, p) in self.panes.iter_mut().enumerate() {
+            if i != self.focus && p.visible {
+                p.draw();
+            }
+        }
+
+        if self.vsplit {
+            stdout
+                .queue(style::SetForegroundColor(style::Color::DarkGrey))
+                .unwrap();
+            let shown = self.panes.iter().filter(|p| p.visible).count();
+            for p in self.panes[..shown.saturating_sub(1)].iter() {
+                for row in 0..p.size.1 {
+                    stdout
+                        .queue(cursor::MoveTo(p.origin.0 + p.size.0, p.origin.1 + row))
+                        .unwrap();
+                    print!("│");
+                }
+            }
+            stdout.queue(style::ResetColor).unwrap();
+        }
+
+        if self.panes[self.focus].visible {
+            self.panes[self.focus].draw();
+        }
+        stdout.flush().unwrap();
+    }
+}
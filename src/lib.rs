@@ -1,2 +1,19 @@
+pub mod batch;
+pub mod buffer;
+pub mod clipboard;
+pub mod diff;
+pub mod filter;
+pub mod hooks;
+pub mod layout;
+pub mod line;
+pub mod notify;
 pub mod opts;
+pub mod preload;
+pub mod process;
+pub mod run;
+pub mod shell;
+pub mod table;
+pub mod tabs;
+pub mod triggers;
 pub mod ui;
+pub mod until;
@@ -0,0 +1,366 @@
+// normalisation of raw output lines into the text a terminal would show.
+//
+// programs that expect a terminal print progress bars by rewriting the line
+// with '\r' and man pages bold/underline text by overstriking characters
+// with '\b'. we replay those onto a row of cells so that only the final
+// visible text ends up on screen, with the overstrikes kept as spans.
+//
+// lines are raw bytes, so anything that isn't valid utf-8 or is a control
+// character is shown as a visible escape (^A, <FF>) instead of garbling the
+// terminal.
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Attr {
+    pub bold: bool,
+    pub underline: bool,
+    // an escape standing in for a control character or invalid byte
+    pub escape: bool,
+}
+
+// a run of text with the same attributes, as byte offsets into the text
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub attr: Attr,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Line {
+    pub text: String,
+    pub spans: Vec<Span>,
+}
+
+impl Line {
+    // the attributes of the text at byte offset i
+    pub fn attr_at(&self, i: usize) -> Attr {
+        self.spans
+            .iter()
+            .find(|s| s.start <= i && i < s.end)
+            .map(|s| s.attr)
+            .unwrap_or_default()
+    }
+}
+
+const TAB_WIDTH: usize = 8;
+
+enum Token {
+    Char(char),
+    Byte(u8),
+}
+
+// write a cell at the current column, handling overstrikes
+fn put(cells: &mut Vec<(char, Attr)>, col: &mut usize, c: char, overstrike: bool) {
+    if *col < cells.len() {
+        let (old, mut attr) = cells[*col];
+        if !overstrike {
+            cells[*col] = (c, Attr::default());
+        } else if old == c {
+            // x\bx
+            attr.bold = true;
+            cells[*col] = (c, attr);
+        } else if old == '_' {
+            // _\bx
+            attr.underline = true;
+            cells[*col] = (c, attr);
+        } else if c == '_' {
+            // x\b_
+            attr.underline = true;
+            cells[*col] = (old, attr);
+        } else {
+            cells[*col] = (c, Attr::default());
+        }
+    } else {
+        cells.push((c, Attr::default()));
+    }
+    *col += 1;
+}
+
+// write a visible escape sequence, these are never overstruck
+fn escape(cells: &mut Vec<(char, Attr)>, col: &mut usize, s: String) {
+    let attr = Attr {
+        escape: true,
+        ..Attr::default()
+    };
+
+    for c in s.chars() {
+        if *col < cells.len() {
+            cells[*col] = (c, attr);
+        } else {
+            cells.push((c, attr));
+        }
+        *col += 1;
+    }
+}
+
+pub fn normalize(raw: &[u8]) -> Line {
+    expand(raw, true)
+}
+
+// the same but with tabs left in, for splitting tsv into cells
+pub fn normalize_cells(raw: &[u8]) -> Line {
+    expand(raw, false)
+}
+
+fn expand(raw: &[u8], tabs: bool) -> Line {
+    let mut cells: Vec<(char, Attr)> = vec![];
+    let mut col: usize = 0;
+    // set after a backspace so that the next write is treated as an
+    // overstrike instead of a plain replacement like after '\r'
+    let mut overstrike = false;
+
+    let tokens = raw.utf8_chunks().flat_map(|chunk| {
+        chunk
+            .valid()
+            .chars()
+            .map(Token::Char)
+            .chain(chunk.invalid().iter().map(|b| Token::Byte(*b)))
+    });
+
+    for t in tokens {
+        let c = match t {
+            Token::Byte(b) => {
+                escape(&mut cells, &mut col, format!("<{b:02X}>"));
+                continue;
+            }
+            Token::Char(c) => c,
+        };
+
+        match c {
+            '\r' => {
+                col = 0;
+                overstrike = false;
+            }
+            '\x08' => {
+                col = col.saturating_sub(1);
+                overstrike = true;
+            }
+            '\t' if !tabs => put(&mut cells, &mut col, c, false),
+            '\t' => {
+                let next = (col / TAB_WIDTH + 1) * TAB_WIDTH;
+                while col < next {
+                    put(&mut cells, &mut col, ' ', false);
+                }
+            }
+            // C0 controls and DEL use caret notation
+            '\x00'..='\x1f' | '\x7f' => {
+                let caret = ((c as u8) ^ 0x40) as char;
+                escape(&mut cells, &mut col, format!("^{caret}"));
+            }
+            // C1 controls
+            _ if c.is_control() => {
+                escape(&mut cells, &mut col, format!("<U+{:04X}>", c as u32));
+            }
+            _ => put(&mut cells, &mut col, c, overstrike),
+        }
+    }
+
+    let mut line = Line::default();
+    for (c, attr) in cells {
+        let start = line.text.len();
+        line.text.push(c);
+        let end = line.text.len();
+
+        if attr == Attr::default() {
+            continue;
+        }
+
+        match line.spans.last_mut() {
+            Some(s) if s.end == start && s.attr == attr => s.end = end,
+            _ => line.spans.push(Span { start, end, attr }),
+        }
+    }
+
+    line
+}
+
+// remove the escape sequences that set colours and move the cursor, eg.
+// ESC[31m, along with OSC sequences like titles and links
+pub fn strip_ansi(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] != 0x1b || i + 1 == raw.len() {
+            out.push(raw[i]);
+            i += 1;
+            continue;
+        }
+
+        i += 2;
+        match raw[i - 1] {
+            // CSI ends with a byte in @..~
+            b'[' => {
+                while i < raw.len() && !(0x40..=0x7e).contains(&raw[i]) {
+                    i += 1;
+                }
+                i += 1;
+            }
+            // OSC ends with BEL or ESC \
+            b']' => {
+                while i < raw.len()
+                    && raw[i] != 0x07
+                    && !(raw[i] == 0x1b && raw.get(i + 1) == Some(&b'\\'))
+                {
+                    i += 1;
+                }
+                i += if raw.get(i) == Some(&0x07) { 1 } else { 2 };
+            }
+            // anything else is a two byte sequence
+            _ => {}
+        }
+    }
+    out
+}
+
+// format a row of a hex dump in the style of `hexdump -C`
+pub fn hexdump(offset: usize, bytes: &[u8]) -> Vec<u8> {
+    let mut row = format!("{offset:08x} ");
+    for i in 0..16 {
+        if i == 8 {
+            row.push(' ');
+        }
+
+        match bytes.get(i) {
+            Some(b) => row.push_str(&format!(" {b:02x}")),
+            None => row.push_str("   "),
+        }
+    }
+
+    row.push_str("  |");
+    for b in bytes {
+        if b.is_ascii_graphic() || *b == b' ' {
+            row.push(*b as char);
+        } else {
+            row.push('.');
+        }
+    }
+    row.push('|');
+
+    row.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(line: &Line) -> Vec<(usize, usize, Attr)> {
+        line.spans
+            .iter()
+            .map(|s| (s.start, s.end, s.attr))
+            .collect()
+    }
+
+    const BOLD: Attr = Attr {
+        bold: true,
+        underline: false,
+        escape: false,
+    };
+    const UNDERLINE: Attr = Attr {
+        bold: false,
+        underline: true,
+        escape: false,
+    };
+    const ESCAPE: Attr = Attr {
+        bold: false,
+        underline: false,
+        escape: true,
+    };
+
+    #[test]
+    fn plain() {
+        let line = normalize("hello, wörld".as_bytes());
+        assert_eq!(line.text, "hello, wörld");
+        assert!(line.spans.is_empty());
+    }
+
+    #[test]
+    fn progress() {
+        assert_eq!(normalize(b"10%\r50%\r100%").text, "100%");
+        // a shorter line only covers the start, as on a terminal
+        assert_eq!(normalize(b"downloading\rdone").text, "doneloading");
+        assert_eq!(normalize(b"[##  ]\r[####]\r").text, "[####]");
+    }
+
+    #[test]
+    fn crlf() {
+        assert_eq!(normalize(b"line\r").text, "line");
+        assert_eq!(normalize(b"\r").text, "");
+    }
+
+    #[test]
+    fn bold() {
+        let line = normalize(b"b\x08bo\x08old");
+        assert_eq!(line.text, "bold");
+        assert_eq!(spans(&line), [(0, 2, BOLD)]);
+        assert_eq!(line.attr_at(1), BOLD);
+        assert_eq!(line.attr_at(2), Attr::default());
+    }
+
+    #[test]
+    fn underline() {
+        let line = normalize(b"_\x08a_\x08b c\x08_");
+        assert_eq!(line.text, "ab c");
+        assert_eq!(spans(&line), [(0, 2, UNDERLINE), (3, 4, UNDERLINE)]);
+
+        // both at once
+        let line = normalize(b"_\x08x\x08x");
+        assert_eq!(line.text, "x");
+        assert_eq!(
+            spans(&line),
+            [(
+                0,
+                1,
+                Attr {
+                    bold: true,
+                    underline: true,
+                    escape: false
+                }
+            )]
+        );
+
+        // a different character just replaces it
+        let line = normalize(b"a\x08b");
+        assert_eq!(line.text, "b");
+        assert!(line.spans.is_empty());
+    }
+
+    #[test]
+    fn tabs() {
+        assert_eq!(normalize(b"a\tb").text, "a       b");
+        assert_eq!(normalize(b"12345678\tb").text, "12345678        b");
+        assert_eq!(normalize_cells(b"a\tb").text, "a\tb");
+    }
+
+    #[test]
+    fn controls() {
+        let line = normalize(b"a\x01b\x7f");
+        assert_eq!(line.text, "a^Ab^?");
+        assert_eq!(spans(&line), [(1, 3, ESCAPE), (4, 6, ESCAPE)]);
+        assert_eq!(normalize(b"\x0c").text, "^L");
+        assert_eq!(normalize("\u{85}".as_bytes()).text, "<U+0085>");
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let line = normalize(b"a\xffb\xc3");
+        assert_eq!(line.text, "a<FF>b<C3>");
+        assert_eq!(spans(&line), [(1, 5, ESCAPE), (6, 10, ESCAPE)]);
+    }
+
+    #[test]
+    fn strip() {
+        assert_eq!(strip_ansi(b"\x1b[1;31mred\x1b[0m"), b"red");
+        assert_eq!(strip_ansi(b"\x1b[2K\x1b[1Gdone"), b"done");
+        // titles end with BEL, links with ESC \
+        assert_eq!(strip_ansi(b"\x1b]0;title\x07text"), b"text");
+        assert_eq!(
+            strip_ansi(b"\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\"),
+            b"link"
+        );
+        assert_eq!(strip_ansi(b"\x1b=x"), b"x");
+        // cut short at the end of the line
+        assert_eq!(strip_ansi(b"a\x1b[31"), b"a");
+        assert_eq!(strip_ansi(b"a\x1b]0;ti"), b"a");
+        assert_eq!(strip_ansi(b"a\x1b"), b"a\x1b");
+    }
+}
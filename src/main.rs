@@ -1,94 +1,130 @@
 use std::io::prelude::*;
-use std::process::Stdio;
 use std::sync::{Arc, Mutex};
 
-use inotify::Inotify;
 use mp::opts::parse_opts;
-use mp::ui;
+use mp::tabs::Tabs;
+use mp::triggers::Trigger;
+use mp::{process, triggers, ui};
 
 fn main() {
-    let opts = parse_opts();
-
-    // build the command
-    let mut cmd = std::process::Command::new("sh");
-    cmd.arg("-c");
-    if let Some(f) = opts.file.as_ref() {
-        cmd.arg(format!("cat {}", f.to_string_lossy()));
-    } else if let Some(c) = opts.cmd.as_ref() {
-        cmd.arg(c);
-    };
+    let panes = parse_opts().into_iter().map(ui::State::new).collect();
 
-    cmd.stdin(Stdio::piped());
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::piped());
+    // before any threads are started, so they all have them blocked
+    let signals = block_signals();
 
     // initialise the ui and surround with arc/mutex for sharing across threads
-    let s = Arc::new(Mutex::new(ui::State::init(cmd, opts)));
-
-    // for each watching operation, start a new thread
-    if let Some(t) = s.lock().unwrap().opts.time {
-        // small stack size cause it just loops
-        let s = s.clone();
-        std::thread::Builder::new()
-            .stack_size(1024)
-            .name("timer".to_string())
-            .spawn(move || {
-                timer_thread(s, t);
-            })
-            .unwrap();
-    }
+    let s = Arc::new(Mutex::new(Tabs::init(panes)));
+    wait_signals(s.clone(), signals);
 
-    if s.lock().unwrap().opts.files.len() > 0 {
-        let s = s.clone();
-        std::thread::spawn(move || {
-            inotify_thread(s);
-        });
+    if s.lock().unwrap().batch() {
+        batch(s);
+    } else {
+        ui_thread(s);
     }
-
-    ui_thread(s);
 }
 
-fn ui_thread(s: Arc<Mutex<ui::State>>) {
-    let mut bytes = std::io::stdin().bytes();
-    while let Some(Ok(c)) = bytes.next() {
-        let mut s = s.lock().unwrap();
+// there are no keys to read in batch mode, the triggers do all the work
+fn batch(s: Arc<Mutex<Tabs>>) {
+    let started = std::mem::take(&mut s.lock().unwrap().started);
+    let watching = s
+        .lock()
+        .unwrap()
+        .tabs
+        .iter()
+        .flat_map(|l| l.panes.iter())
+        .any(|p| p.opts.time.is_some() || !p.opts.files.is_empty());
 
-        s.event(c as char);
-        s.draw();
+    // the first reads are done one after another so the output comes out in
+    // order. the signals are being waited for by now, so these can be stopped
+    for &id in &started {
+        triggers::update(&s, id, Trigger::Start);
+    }
+    for id in started {
+        triggers::spawn(s.clone(), id);
     }
 
-    s.lock().unwrap().exit();
+    if watching {
+        loop {
+            std::thread::park();
+        }
+    }
+
+    // nothing will update, so an until condition can't be met any more
+    s.lock().unwrap().tabs[0].panes[0].exit();
 }
 
-fn timer_thread(s: Arc<Mutex<ui::State>>, t: f64) {
-    loop {
-        std::thread::sleep(std::time::Duration::from_secs_f64(t));
-        let mut state = s.lock().unwrap();
-        state.update();
-        state.draw();
+// the commands are in process groups of their own, so they don't get the
+// signals that stop mp. block them here and wait for them on a thread, which
+// can kill the commands first
+fn block_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for sig in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            libc::sigaddset(&mut set, sig);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        set
     }
 }
 
-fn inotify_thread(s: Arc<Mutex<ui::State>>) {
-    let mut inotify = Inotify::init().unwrap();
-
-    // add the files to be watched
-    {
-        let state = s.lock().unwrap();
-        for f in state.opts.files.iter() {
-            inotify
-                .watches()
-                .add(f, inotify::WatchMask::MODIFY)
-                .unwrap();
+fn wait_signals(s: Arc<Mutex<Tabs>>, set: libc::sigset_t) {
+    std::thread::spawn(move || {
+        let mut sig = 0;
+        unsafe {
+            libc::sigwait(&set, &mut sig);
         }
-    }
 
-    // wait for changes
-    let mut buffer = [0; 1024];
+        // killing the commands lets any update that is stuck on one finish
+        process::kill_all();
+        let mut s = s.lock().unwrap();
+        let curr = s.current;
+        s.tabs[curr].panes[0].leave(128 + sig);
+    });
+}
+
+fn ui_thread(s: Arc<Mutex<Tabs>>) {
+    let mut bytes = std::io::stdin().lock().bytes();
     loop {
-        inotify.read_events_blocking(&mut buffer).unwrap();
-        let mut state = s.lock().unwrap();
-        state.update();
-        state.draw();
+        // start watching any panes that were opened
+        let started = std::mem::take(&mut s.lock().unwrap().started);
+        for id in started {
+            triggers::spawn(s.clone(), id);
+        }
+
+        let Some(c) = next_char(&mut bytes) else {
+            break;
+        };
+
+        let mut s = s.lock().unwrap();
+        s.event(c);
+        s.draw();
+    }
+
+    let mut s = s.lock().unwrap();
+    let curr = s.current;
+    s.tabs[curr].panes[0].exit();
+}
+
+// read a key, putting together the bytes of a character typed into a prompt
+// so that text other than ascii comes through whole
+fn next_char(bytes: &mut impl Iterator<Item = std::io::Result<u8>>) -> Option<char> {
+    let b = bytes.next()?.ok()?;
+    let len = match b {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Some(b as char),
+    };
+
+    let mut buf = vec![b];
+    for _ in 1..len {
+        buf.push(bytes.next()?.ok()?);
     }
+    Some(
+        std::str::from_utf8(&buf)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER),
+    )
 }
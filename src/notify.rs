@@ -0,0 +1,91 @@
+// getting the attention of whoever is running mp, for when they are looking
+// at another window
+
+use std::io::Write;
+
+use regex::Regex;
+
+// what to alert on
+#[derive(Clone)]
+pub enum Alert {
+    // the output is different to the last update
+    Change,
+    // a line matches that didn't in the last update
+    Match(Regex),
+}
+
+impl Alert {
+    // whether to alert for new output, and a description of why
+    pub fn fired(&self, old: &[String], new: &[String]) -> Option<String> {
+        match self {
+            Alert::Change if old != new => Some("output changed".into()),
+            Alert::Match(re) => {
+                let matched = |lines: &[String]| lines.iter().any(|l| re.is_match(l));
+                if !matched(old) && matched(new) {
+                    Some(format!("output matched '{re}'"))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+// the escape used for desktop notifications. osc 9 is understood by iTerm2,
+// kitty and wezterm, osc 777 by the vte based terminals and urxvt
+#[derive(Clone, Copy)]
+pub enum Notify {
+    Osc9,
+    Osc777,
+}
+
+// the terminal, even when stdout is going somewhere else in batch mode
+fn tty() -> Box<dyn Write> {
+    match std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(f) => Box::new(f),
+        Err(_) => Box::new(std::io::stdout()),
+    }
+}
+
+// write an escape sequence to the terminal. tmux only passes escapes through
+// to the outer terminal when wrapped, with any escape characters inside
+// doubled
+pub fn send(seq: &str) {
+    let seq = if std::env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", seq.replace('\x1b', "\x1b\x1b"))
+    } else {
+        seq.to_string()
+    };
+
+    let mut tty = tty();
+    let _ = tty.write_all(seq.as_bytes());
+    let _ = tty.flush();
+}
+
+// the message in an escape ends at a bell or escape
+fn clean(msg: &str) -> String {
+    msg.replace(['\x07', '\x1b'], " ")
+}
+
+pub fn bell() {
+    // tmux handles the bell itself, so it goes out as is
+    let mut tty = tty();
+    let _ = tty.write_all(b"\x07");
+    let _ = tty.flush();
+}
+
+pub fn notify(kind: Notify, title: &str, msg: &str) {
+    let (title, msg) = (clean(title), clean(msg));
+    send(&match kind {
+        Notify::Osc9 => format!("\x1b]9;{title}: {msg}\x07"),
+        Notify::Osc777 => format!("\x1b]777;notify;{title};{msg}\x07"),
+    });
+}
+
+// set the title of the terminal window, or of the pane inside tmux
+pub fn title(title: &str) {
+    let mut tty = tty();
+    let _ = write!(tty, "\x1b]2;{}\x07", clean(title));
+    let _ = tty.flush();
+}
@@ -1,13 +1,63 @@
+use std::iter::Peekable;
 use std::path::PathBuf;
 
+use regex::Regex;
+
+use crate::buffer::Keep;
+use crate::notify::{Alert, Notify};
+use crate::preload;
+use crate::shell;
+use crate::table::Format;
+use crate::until::Until;
+
 fn version() {
     eprintln!("mp 0.0.1");
     std::process::exit(0);
 }
 
-fn usage(code: i32) {
+// print the setup for a shell so that `... | mp` works without the preload
+fn shell_init(shell: Option<&str>) -> ! {
+    match shell.and_then(shell::init) {
+        Some(s) => print!("{s}"),
+        None => {
+            eprintln!("shell-init option expects bash, zsh or fish\n");
+            usage(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+// rewrite a command line for the shell setup, printing it as it was if it
+// isn't piped into mp
+fn rewrite(shell: Option<String>, line: Option<String>) -> ! {
+    let (Some(shell), Some(line)) = (shell, line) else {
+        eprintln!("rewrite option expects a shell and a command line\n");
+        usage(1);
+    };
+
+    match shell::rewrite(&line, shell == "fish") {
+        Some(l) => println!("{l}"),
+        None => println!("{line}"),
+    }
+    std::process::exit(0);
+}
+
+// print where the readline shim is, writing it out if needed
+fn preload_path() -> ! {
+    match preload::path() {
+        Ok(p) => println!("{}", p.to_string_lossy()),
+        Err(e) => {
+            eprintln!("failed to write the preload library: {e}");
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+fn usage(code: i32) -> ! {
     eprintln!("Usage: mp [OPTION]... [FILE]");
     eprintln!("       mp [OPTION]... -- [COMMAND]");
+    eprintln!("       mp [OPTION]... -- [COMMAND] --- [OPTION]... [COMMAND]...");
     eprintln!("Display the output of a file or command in the terminal.");
     eprintln!("Update the output on events selected by options.\n");
 
@@ -16,13 +66,63 @@ fn usage(code: i32) {
     eprintln!("  -t, --time=n     update every n seconds");
     eprintln!("  -f, --file=f     update when file f changes (default when file specified)");
     eprintln!("  -d, --dir=d      update when any file in dir d changes");
-    eprintln!("  -s, --size       update when the terminal size changes\n");
+    eprintln!("  -s, --size       update when the terminal size changes");
+    eprintln!("  -F, --tail       only read lines appended to the file and follow the end\n");
 
     eprintln!("  -e, --errexit    exit if command has a non-zero exit");
-    eprintln!("  --diff           highlight changes between updates\n");
+    eprintln!("  --diff           highlight changes between updates");
+    eprintln!("  -x, --hex        display the output as a hex dump");
+    eprintln!("  -m, --max-lines=n  keep only the last n lines of output");
+    eprintln!("  --head           keep the first lines instead of the last with --max-lines");
+    eprintln!("  --header=n[,c]   keep the first n lines and c columns visible while scrolling");
+    eprintln!("  --table[=fmt]    show the output as a table, fmt is csv, tsv or ws (aligned)");
+    eprintln!("  -p, --pipe=cmd   pipe the output through cmd before showing it");
+    eprintln!("  --timeout=n      kill the command if it runs for more than n seconds");
+    eprintln!("  -k, --keep       keep showing the last output when the command fails or prints");
+    eprintln!("                   nothing, E shows what the failed run wrote to stderr");
+    eprintln!("  -C, --cwd=dir    run the command in dir");
+    eprintln!("  -E, --env=k=v    set the variable k to v for the command");
+    eprintln!("  --env-file=f     set the variables in f, one k=v on each line");
+    eprintln!("  -o, --output=f   write the output to file f after each update");
+    eprintln!("  --raw            write the output exactly as read, keeping colours");
+    eprintln!("  -b, --batch      print each update to stdout instead of using the terminal");
+    eprintln!("  --changes        in batch mode, only print updates that changed the output");
+    eprintln!("  --until=re       exit once a line of the output matches re");
+    eprintln!("  --until-not=re   exit once no line of the output matches re");
+    eprintln!("  --until-exit=n   exit once the command exits with status n");
+    eprintln!("  --until-change   exit once the output is different to the first time");
+    eprintln!("  --alert[=re]     alert when the output changes, or when a line first matches re");
+    eprintln!("  --bell           ring the bell on an alert or when an until condition is met");
+    eprintln!("  --notify[=kind]  send a desktop notification as well, kind is osc9 or osc777");
+    eprintln!("  --set-title      put the reason in the terminal title as well");
+    eprintln!("  --alert-cmd=cmd  run cmd on an alert, with the changed lines on stdin");
+    eprintln!("  --on-update=cmd  run cmd after each update, with the output on stdin");
+    eprintln!("  --on-success=cmd run cmd after each update where the command succeeded");
+    eprintln!("  --on-fail=cmd    run cmd after each update where the command failed");
+    eprintln!("  -v, --vsplit     show multiple commands side by side instead of stacked");
+    eprintln!("  -T, --tabs       show multiple commands in tabs instead of split\n");
+
+    eprintln!("  --shell-init=sh  print the setup for piping into mp from bash, zsh or fish,");
+    eprintln!("                   eg. eval \"$(mp --shell-init=bash)\" in ~/.bashrc");
+    eprintln!("  --rewrite sh l   rewrite the command line l for sh, as the setup does");
+    eprintln!("  --print-preload-path  print the path of the readline library that does the");
+    eprintln!("                   same in bash, eg. LD_PRELOAD=$(mp --print-preload-path) bash.");
+    eprintln!("                   it only rewrites in shells from /etc/shells, unless");
+    eprintln!("                   MP_PRELOAD_FORCE is set to 1, or 0 to turn it off. the");
+    eprintln!("                   line as typed goes in the history unless MP_PRELOAD_HISTORY=0\n");
 
     eprintln!("  -h, --help       display this help message");
-    eprintln!("  --version        display the program version");
+    eprintln!("  --version        display the program version\n");
+
+    eprintln!("Each --- starts another pane. A -- after the command is passed on to it, eg.");
+    eprintln!("mp -- git log -- src.\n");
+
+    eprintln!("With an until option, mp exits 0 once the condition is met and 1 if it");
+    eprintln!("is quit before then.\n");
+
+    eprintln!("Commands run on an update get MP_TITLE, MP_TRIGGER (start, timer or file),");
+    eprintln!("MP_EXIT_CODE and MP_DURATION (in seconds) in their environment. The command");
+    eprintln!("itself gets MP_ITERATION, MP_TRIGGER and MP_LAST_STATUS.");
 
     std::process::exit(code);
 }
@@ -34,30 +134,225 @@ pub struct Options {
     pub size: bool,
     pub errexit: bool,
     pub diff: bool,
+    pub hex: bool,
+    pub max_lines: Option<(usize, Keep)>,
     pub never: bool,
+    pub tail: bool,
+    pub vsplit: bool,
+    pub tabs: bool,
+    pub header: (usize, usize),
+    pub table: Option<Format>,
+    pub output: Option<PathBuf>,
+    pub raw: bool,
+    pub batch: bool,
+    pub changes: bool,
+    pub until: Option<Until>,
+    pub alert: Option<Alert>,
+    pub bell: bool,
+    pub notify: Option<Notify>,
+    pub set_title: bool,
+    pub alert_cmd: Option<String>,
+    pub on_update: Option<String>,
+    pub on_success: Option<String>,
+    pub on_fail: Option<String>,
     pub file: Option<PathBuf>,
     pub cmd: Option<String>,
+    pub pipe: Option<String>,
+    pub cwd: Option<PathBuf>,
+    pub timeout: Option<f64>,
+    pub keep: bool,
+    pub env: Vec<(String, String)>,
 }
 
-pub fn parse_opts() -> Options {
+// parse the options for every pane. each --- starts another pane, which
+// takes the options given before the first command and can change them with
+// options before its own command. it isn't -- so that commands can still
+// have a -- of their own
+pub fn parse_opts() -> Vec<Options> {
+    let mut args = std::env::args().skip(1).peekable();
+
     let mut opts = Options {
         time: None,
         files: vec![],
         size: false,
         errexit: false,
         diff: false,
+        hex: false,
+        max_lines: None,
         never: false,
+        tail: false,
+        vsplit: false,
+        tabs: false,
+        header: (0, 0),
+        table: None,
+        output: None,
+        raw: false,
+        batch: false,
+        changes: false,
+        until: None,
+        alert: None,
+        bell: false,
+        notify: None,
+        set_title: false,
+        alert_cmd: None,
+        on_update: None,
+        on_success: None,
+        on_fail: None,
         file: None,
         cmd: None,
+        pipe: None,
+        cwd: None,
+        timeout: None,
+        keep: false,
+        env: vec![],
     };
 
-    let mut args = std::env::args();
-    args.next().unwrap();
+    // whether --max-lines keeps the head, applied once all options are read
+    let mut head = false;
+    parse_args(&mut opts, &mut head, &mut args, false);
+
+    let mut panes = vec![check(opts.clone(), head)];
+    while args.next_if(|a| a == PANE).is_some() {
+        // each pane needs its own command and output file
+        let mut pane = Options {
+            time: None,
+            file: None,
+            cmd: None,
+            pipe: None,
+            output: None,
+            ..opts.clone()
+        };
+        let mut head = head;
+        parse_args(&mut pane, &mut head, &mut args, true);
+
+        if pane.time.is_none() {
+            pane.time = opts.time;
+        }
+        panes.push(check(pane, head));
+    }
+
+    panes
+}
+
+// parse the lines and optional columns of a header, eg. 1 or 1,2
+fn parse_header(s: &str) -> Option<(usize, usize)> {
+    match s.split_once(',') {
+        Some((rows, cols)) => Some((rows.parse().ok()?, cols.parse().ok()?)),
+        None => Some((s.parse().ok()?, 0)),
+    }
+}
+
+// parse the value of one of the until options
+fn parse_until(opts: &mut Options, key: &str, val: Option<String>) {
+    if opts.until.is_some() {
+        eprintln!("until option specified multiple times\n");
+        usage(1);
+    }
+
+    let Some(val) = val else {
+        eprintln!("argument expected for {} option\n", &key[2..]);
+        usage(1);
+    };
+
+    opts.until = Some(match key {
+        "--until-exit" => match val.parse::<i32>() {
+            Ok(c) => Until::Exit(c),
+            Err(_) => {
+                eprintln!("numeric value expected for until-exit argument\n");
+                usage(1);
+            }
+        },
+        _ => match Regex::new(&val) {
+            Ok(re) if key == "--until" => Until::Match(re),
+            Ok(re) => Until::NoMatch(re),
+            Err(e) => {
+                eprintln!("invalid pattern for {} option: {e}\n", &key[2..]);
+                usage(1);
+            }
+        },
+    });
+}
+
+// the command to run for one of the hook options
+fn hook<'a>(opts: &'a mut Options, key: &str) -> &'a mut Option<String> {
+    match key {
+        "--on-success" => &mut opts.on_success,
+        "--on-fail" => &mut opts.on_fail,
+        _ => &mut opts.on_update,
+    }
+}
+
+// set a variable for the command from k=v
+fn parse_env(opts: &mut Options, s: &str) {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => opts.env.push((k.into(), v.into())),
+        _ => {
+            eprintln!("env option expects k=v, not '{s}'\n");
+            usage(1);
+        }
+    }
+}
+
+// set the variables in a file like .env, which has a k=v on each line. the
+// lines can start with export and the values can be quoted
+fn parse_env_file(opts: &mut Options, path: &str) {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("failed to read env file '{path}': {e}\n");
+            usage(1);
+        }
+    };
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((k, v)) = line.split_once('=') else {
+            eprintln!("env file '{path}' has a line without '=': {line}\n");
+            usage(1);
+        };
+
+        let v = v.trim();
+        let v = match (v.chars().next(), v.chars().last()) {
+            (Some(q @ ('"' | '\'')), Some(e)) if v.len() > 1 && q == e => &v[1..v.len() - 1],
+            _ => v,
+        };
+        opts.env.push((k.trim().into(), v.into()));
+    }
+}
+
+// what goes between the panes on the command line
+const PANE: &str = "---";
+
+// take the words of a command up to the next pane
+fn take_cmd(args: &mut Peekable<impl Iterator<Item = String>>) -> String {
+    let mut words = vec![];
+    while let Some(arg) = args.next_if(|a| a != PANE) {
+        words.push(arg);
+    }
+    words.join(" ")
+}
+
+// parse options until the file or command is found. in a pane after the
+// first, the first word that isn't an option starts the command
+fn parse_args(
+    opts: &mut Options,
+    head: &mut bool,
+    args: &mut Peekable<impl Iterator<Item = String>>,
+    pane: bool,
+) {
     while let Some(arg) = args.next() {
         // the basic matches
         match arg.as_str() {
             "-h" | "--help" => usage(0),
             "--version" => version(),
+            "--shell-init" => shell_init(args.next().as_deref()),
+            "--rewrite" => rewrite(args.next(), args.next()),
+            "--print-preload-path" => preload_path(),
             "-0" | "--never" => {
                 opts.never = true;
                 continue;
@@ -98,6 +393,14 @@ pub fn parse_opts() -> Options {
                 opts.size = true;
                 continue;
             }
+            "-F" | "--tail" => {
+                opts.tail = true;
+                continue;
+            }
+            "-k" | "--keep" => {
+                opts.keep = true;
+                continue;
+            }
             "-e" | "--errexit" => {
                 opts.errexit = true;
                 continue;
@@ -106,11 +409,170 @@ pub fn parse_opts() -> Options {
                 opts.diff = true;
                 continue;
             }
+            "-x" | "--hex" => {
+                opts.hex = true;
+                continue;
+            }
+            "-m" | "--max-lines" => {
+                if let Some(Ok(n)) = args.next().map(|s| s.parse::<usize>()) {
+                    opts.max_lines = Some((n, Keep::Tail));
+                    continue;
+                } else {
+                    eprintln!("numeric value expected for max-lines argument\n");
+                    usage(1);
+                }
+            }
+            "--head" => {
+                *head = true;
+                continue;
+            }
+            "-v" | "--vsplit" => {
+                opts.vsplit = true;
+                continue;
+            }
+            "-T" | "--tabs" => {
+                opts.tabs = true;
+                continue;
+            }
+            "--table" => {
+                opts.table = Some(Format::Auto);
+                continue;
+            }
+            "-p" | "--pipe" => {
+                if let Some(arg) = args.next() {
+                    opts.pipe = Some(arg);
+                    continue;
+                } else {
+                    eprintln!("argument expected for pipe option\n");
+                    usage(1);
+                }
+            }
+            "--timeout" => {
+                if let Some(Ok(t)) = args.next().map(|s| s.parse::<f64>()) {
+                    opts.timeout = Some(t);
+                    continue;
+                } else {
+                    eprintln!("numeric value expected for timeout argument\n");
+                    usage(1);
+                }
+            }
+            "-C" | "--cwd" => {
+                if let Some(arg) = args.next() {
+                    opts.cwd = Some(arg.into());
+                    continue;
+                } else {
+                    eprintln!("argument expected for cwd option\n");
+                    usage(1);
+                }
+            }
+            "-E" | "--env" => {
+                if let Some(arg) = args.next() {
+                    parse_env(opts, &arg);
+                    continue;
+                } else {
+                    eprintln!("argument expected for env option\n");
+                    usage(1);
+                }
+            }
+            "--env-file" => {
+                if let Some(arg) = args.next() {
+                    parse_env_file(opts, &arg);
+                    continue;
+                } else {
+                    eprintln!("argument expected for env-file option\n");
+                    usage(1);
+                }
+            }
+            "-o" | "--output" => {
+                if let Some(arg) = args.next() {
+                    opts.output = Some(arg.into());
+                    continue;
+                } else {
+                    eprintln!("argument expected for output option\n");
+                    usage(1);
+                }
+            }
+            "--raw" => {
+                opts.raw = true;
+                continue;
+            }
+            "-b" | "--batch" => {
+                opts.batch = true;
+                continue;
+            }
+            "--changes" => {
+                opts.changes = true;
+                continue;
+            }
+            "--until" | "--until-not" | "--until-exit" => {
+                parse_until(opts, &arg, args.next());
+                continue;
+            }
+            "--until-change" => {
+                if opts.until.is_some() {
+                    eprintln!("until option specified multiple times\n");
+                    usage(1);
+                }
+                opts.until = Some(Until::Change);
+                continue;
+            }
+            "--bell" => {
+                opts.bell = true;
+                continue;
+            }
+            "--alert" => {
+                opts.alert = Some(Alert::Change);
+                continue;
+            }
+            "--notify" => {
+                opts.notify = Some(Notify::Osc9);
+                continue;
+            }
+            "--set-title" => {
+                opts.set_title = true;
+                continue;
+            }
+            "--alert-cmd" => {
+                if let Some(arg) = args.next() {
+                    opts.alert_cmd = Some(arg);
+                    continue;
+                } else {
+                    eprintln!("argument expected for alert-cmd option\n");
+                    usage(1);
+                }
+            }
+            "--on-update" | "--on-success" | "--on-fail" => {
+                if let Some(cmd) = args.next() {
+                    *hook(opts, &arg) = Some(cmd);
+                    continue;
+                } else {
+                    eprintln!("argument expected for {} option\n", &arg[2..]);
+                    usage(1);
+                }
+            }
+            "--header" => {
+                if let Some(h) = args.next().and_then(|s| parse_header(&s)) {
+                    opts.header = h;
+                    continue;
+                } else {
+                    eprintln!("numeric value expected for header argument\n");
+                    usage(1);
+                }
+            }
             "--" => {
-                opts.cmd = Some(args.collect::<Vec<_>>().join(" "));
+                opts.cmd = Some(take_cmd(args));
                 break;
             }
             _ if arg.starts_with("-") => {}
+            _ if pane => {
+                let rest = take_cmd(args);
+                opts.cmd = Some(if rest.is_empty() {
+                    arg
+                } else {
+                    format!("{arg} {rest}")
+                });
+                break;
+            }
             file => {
                 opts.file = Some(file.into());
                 break;
@@ -142,6 +604,99 @@ pub fn parse_opts() -> Options {
                     opts.files.push(val.to_string().into());
                     continue;
                 }
+                "--output" => {
+                    opts.output = Some(val.to_string().into());
+                    continue;
+                }
+                "--pipe" => {
+                    opts.pipe = Some(val.to_string());
+                    continue;
+                }
+                "--cwd" => {
+                    opts.cwd = Some(val.to_string().into());
+                    continue;
+                }
+                "--timeout" => {
+                    if let Ok(t) = val.parse::<f64>() {
+                        opts.timeout = Some(t);
+                        continue;
+                    } else {
+                        eprintln!("numeric value expected for timeout argument\n");
+                        usage(1);
+                    }
+                }
+                "--env" => {
+                    parse_env(opts, val);
+                    continue;
+                }
+                "--env-file" => {
+                    parse_env_file(opts, val);
+                    continue;
+                }
+                "--until" | "--until-not" | "--until-exit" => {
+                    parse_until(opts, key, Some(val.to_string()));
+                    continue;
+                }
+                "--alert" => match Regex::new(val) {
+                    Ok(re) => {
+                        opts.alert = Some(Alert::Match(re));
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("invalid pattern for alert option: {e}\n");
+                        usage(1);
+                    }
+                },
+                "--notify" => {
+                    opts.notify = Some(match val {
+                        "osc9" => Notify::Osc9,
+                        "osc777" => Notify::Osc777,
+                        _ => {
+                            eprintln!("notify kind must be osc9 or osc777\n");
+                            usage(1);
+                        }
+                    });
+                    continue;
+                }
+                "--shell-init" => shell_init(Some(val)),
+                "--alert-cmd" => {
+                    opts.alert_cmd = Some(val.to_string());
+                    continue;
+                }
+                "--on-update" | "--on-success" | "--on-fail" => {
+                    *hook(opts, key) = Some(val.to_string());
+                    continue;
+                }
+                "--table" => {
+                    opts.table = Some(match val {
+                        "csv" => Format::Csv,
+                        "tsv" => Format::Tsv,
+                        "ws" => Format::Columns,
+                        _ => {
+                            eprintln!("table format must be csv, tsv or ws\n");
+                            usage(1);
+                        }
+                    });
+                    continue;
+                }
+                "--header" => {
+                    if let Some(h) = parse_header(val) {
+                        opts.header = h;
+                        continue;
+                    } else {
+                        eprintln!("numeric value expected for header argument\n");
+                        usage(1);
+                    }
+                }
+                "--max-lines" => {
+                    if let Ok(n) = val.parse::<usize>() {
+                        opts.max_lines = Some((n, Keep::Tail));
+                        continue;
+                    } else {
+                        eprintln!("numeric value expected for max-lines argument\n");
+                        usage(1);
+                    }
+                }
                 _ => {
                     eprintln!("unrecognized option: {arg}\n");
                     usage(1);
@@ -166,19 +721,88 @@ pub fn parse_opts() -> Options {
             }
         }
     }
-    // check that the options are valid and that paths exist
+}
+
+// check that the options are valid and that paths exist
+fn check(mut opts: Options, head: bool) -> Options {
     if opts.cmd.is_none() && opts.file.is_none() {
         eprintln!("must specify a file or a command\n");
         usage(1);
     }
 
-    if opts.never
-        && (opts.files.len() > 0 || opts.files.len() > 0 || opts.size || opts.time.is_some())
-    {
+    if opts.never && (!opts.files.is_empty() || opts.size || opts.time.is_some()) {
         eprintln!("cannot specify never with other update options\n");
         usage(1);
     }
 
+    if opts.tail && opts.file.is_none() {
+        eprintln!("tail option requires a file\n");
+        usage(1);
+    }
+
+    if (opts.cwd.is_some() || !opts.env.is_empty()) && opts.cmd.is_none() {
+        eprintln!("cwd and env options require a command\n");
+        usage(1);
+    }
+
+    if let Some(d) = opts.cwd.as_ref() {
+        if !d.is_dir() {
+            eprintln!("directory '{}' does not exist\n", d.to_string_lossy());
+            usage(1);
+        }
+    }
+
+    if opts.tail && opts.pipe.is_some() {
+        eprintln!("cannot specify tail with pipe\n");
+        usage(1);
+    }
+
+    if opts.tail && opts.hex {
+        eprintln!("cannot specify tail with hex\n");
+        usage(1);
+    }
+
+    if matches!(opts.until, Some(Until::Exit(_))) && opts.cmd.is_none() {
+        eprintln!("until-exit option requires a command\n");
+        usage(1);
+    }
+
+    let alerting = opts.bell || opts.notify.is_some() || opts.set_title;
+    if alerting && opts.until.is_none() && opts.alert.is_none() {
+        eprintln!("bell, notify and set-title options require an alert or until option\n");
+        usage(1);
+    }
+
+    if opts.alert_cmd.is_some() && opts.alert.is_none() {
+        eprintln!("alert-cmd option requires an alert option\n");
+        usage(1);
+    }
+
+    if (opts.on_success.is_some() || opts.on_fail.is_some()) && opts.cmd.is_none() {
+        eprintln!("on-success and on-fail options require a command\n");
+        usage(1);
+    }
+
+    // a watchdog can't wait for a negative or endless time
+    if opts.timeout.is_some_and(|t| !(t.is_finite() && t > 0.0)) {
+        eprintln!("numeric value expected for timeout argument\n");
+        usage(1);
+    }
+
+    if opts.max_lines.is_some_and(|(n, _)| n == 0) {
+        eprintln!("max-lines must be at least 1\n");
+        usage(1);
+    }
+
+    if head {
+        if let Some((n, _)) = opts.max_lines {
+            opts.max_lines = Some((n, Keep::Head));
+        } else {
+            eprintln!("head option requires max-lines\n");
+            usage(1);
+        }
+    }
+
     // now that we checked "never" we can put the file in the watch list
     if let Some(f) = opts.file.as_ref() {
         if !opts.never {
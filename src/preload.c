@@ -1,4 +1,5 @@
 #define _GNU_SOURCE
+#include <ctype.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
@@ -10,125 +11,379 @@
 
 // Function pointer to the original readline
 char *(*og_readline)(const char *prompt) = NULL;
-// Are we being executed by a shell
-bool in_shell = false;
-
-// copy src into destination escaping any single quotes
-char *stpcpy_escape(char *dst, char *src) {
-    while (*src != 0) {
-        if (*src == '\'') {
-            dst[0] = '\'';
-            dst[1] = '\\';
-            dst[2] = '\'';
-            dst[3] = '\'';
-            dst += 4;
+// and to readline's add_history, if there is one
+void (*og_add_history)(const char *line) = NULL;
+// readline's history entries start with the line, which is all we look at
+typedef struct { char *line; } hist_entry;
+// the rest of readline's history api, for taking the rewritten line back out
+int *og_history_length = NULL;
+hist_entry **(*og_history_list)(void) = NULL;
+hist_entry *(*og_remove_history)(int which) = NULL;
+void *(*og_free_history_entry)(hist_entry *entry) = NULL;
+// the rewritten line last handed back, which the shell adds to the history
+// unless HISTCONTROL has ignorespace
+char *rewritten = NULL;
+// Are we being executed by a shell, -1 until checked
+int in_shell = -1;
+
+// copy the text from start to end into dst in single quotes, escaping any
+// single quotes in it
+char *stpcpy_quoted(char *dst, const char *start, const char *end) {
+    *dst++ = '\'';
+    for (const char *c=start; c!=end; c++) {
+        if (*c == '\'') {
+            dst = stpcpy(dst, "'\\''");
         } else {
-            dst[0] = *src;
-            dst++;
+            *dst++ = *c;
         }
-        src++;
     }
+    *dst++ = '\'';
 
-    dst[0] = 0;
+    *dst = 0;
     return dst;
 }
 
+// move start and end in past any blanks
+void trim(const char **start, const char **end) {
+    while (*start < *end && (**start == ' ' || **start == '\t'))
+        (*start)++;
+    while (*end > *start && ((*end)[-1] == ' ' || (*end)[-1] == '\t'))
+        (*end)--;
+}
+
+// whether the stage of a pipeline starting at s is the "mp" binary
+bool is_mp(const char *s) {
+    while (*s == ' ' || *s == '\t')
+        s++;
+    return strncmp(s, "mp", 2) == 0 && strchr(" \t|", s[2]);
+}
+
+// where the last pipeline of a command line starts, the pipe into the first
+// mp in it and the pipe out of that mp
+struct split {
+    const char *line;
+    const char *list;
+    const char *mp;
+    const char *after;
+};
+
+const char *scan(const char *s, char close, struct split *sp);
+
+// whether s is at the start of a word
+bool word_start(const char *s, const char *line) {
+    return s == line || strchr(" \t\n;&|()", s[-1]);
+}
+
+// skip a '...' string, s points after the opening quote
+const char *skip_single(const char *s) {
+    s = strchr(s, '\'');
+    return s ? s+1 : NULL;
+}
+
+// skip a $'...' string, where quotes can be escaped with a backslash
+const char *skip_ansi(const char *s) {
+    while (*s && *s != '\'')
+        s += (s[0] == '\\' && s[1]) ? 2 : 1;
+    return *s ? s+1 : NULL;
+}
+
+// skip a `...` substitution
+const char *skip_backtick(const char *s) {
+    while (*s && *s != '`')
+        s += (s[0] == '\\' && s[1]) ? 2 : 1;
+    return *s ? s+1 : NULL;
+}
+
+// skip a "..." string, which can have substitutions with their own quotes
+// inside
+const char *skip_double(const char *s, struct split *sp) {
+    while (s && *s != '"') {
+        if (*s == 0)
+            return NULL;
+        else if (s[0] == '\\' && s[1])
+            s += 2;
+        else if (s[0] == '$' && s[1] == '(')
+            s = scan(s+2, ')', sp);
+        else if (s[0] == '$' && s[1] == '{')
+            s = scan(s+2, '}', sp);
+        else if (s[0] == '`')
+            s = skip_backtick(s+1);
+        else
+            s++;
+    }
+    return s ? s+1 : NULL;
+}
+
+// scan a command line up to the close character, or the end when close is
+// 0. at the top level, the start of the last pipeline and the pipes around
+// mp in it are recorded. returns the character after close, or NULL if the line
+// can't be followed, eg. an unclosed quote, a comment or a |&
+const char *scan(const char *s, char close, struct split *sp) {
+    bool top = close == 0;
+
+    while (s) {
+        char c = *s;
+        if (c == 0)
+            return top ? s : NULL;
+        if (!top && c == close)
+            return s+1;
+
+        if (c == '\\') {
+            // a backslash at the end continues onto another line
+            s = s[1] ? s+2 : NULL;
+        } else if (c == '\'') {
+            s = skip_single(s+1);
+        } else if (c == '"') {
+            s = skip_double(s+1, sp);
+        } else if (c == '`') {
+            s = skip_backtick(s+1);
+        } else if (c == '$' && s[1] == '\'') {
+            s = skip_ansi(s+2);
+        } else if (c == '$' && s[1] == '(') {
+            s = scan(s+2, ')', sp);
+        } else if (c == '$' && s[1] == '{') {
+            s = scan(s+2, '}', sp);
+        } else if (c == '(') {
+            // subshells and process substitution
+            s = scan(s+1, ')', sp);
+        } else if (c == '{' && word_start(s, sp->line) && (s[1] == ' ' || s[1] == '\t')) {
+            // a group of commands
+            s = scan(s+1, '}', sp);
+        } else if (c == '#' && word_start(s, sp->line)) {
+            return NULL;
+        } else if (!top) {
+            s++;
+        } else if (c == '|' && s > sp->line && s[-1] == '>') {
+            // the >| redirection
+            s++;
+        } else if (c == '|' && s[1] == '&') {
+            return NULL;
+        } else if (c == '|' && s[1] == '|') {
+            sp->list = s+2;
+            sp->mp = sp->after = NULL;
+            s += 2;
+        } else if (c == '|') {
+            if (sp->mp && !sp->after)
+                sp->after = s;
+            else if (!sp->mp && is_mp(s+1))
+                sp->mp = s;
+            s++;
+        } else if (c == '&' && (s[1] == '>' || (s > sp->line && strchr("<>", s[-1])))) {
+            // redirections like &> and 2>&1
+            s++;
+        } else if (c == '&' || c == ';' || c == '\n') {
+            // && is two of these in a row
+            sp->list = s+1;
+            sp->mp = sp->after = NULL;
+            s++;
+        } else {
+            s++;
+        }
+    }
+
+    return NULL;
+}
+
 char *rewrite(char *input) {
-    // rewrite the command string to take the last "mp" pipe and
+    // rewrite the command string to take the "mp" in a pipeline and
     // wrap the whole expression in it, eg:
     //   cat /etc/passwd | grep root | mp -t5
-    //   mp -t5 'cat /etc/passwd | grep root'
+    //   mp -t5 -- 'cat /etc/passwd | grep root'
+    // commands after mp have each update piped through them:
+    //   ps aux | mp -t1 | grep ssh
+    //   mp -t1 --pipe 'grep ssh' -- 'ps aux'
+    // only the last pipeline on the line is rewritten, so
+    //   cd /var/log && tail syslog | mp
+    //   cd /var/log && mp -- 'tail syslog'
 
-    // find the location of the last pipe
-    char *pipe = strrchr(input, '|');
-    if (!pipe)
+    struct split sp = { input, input, NULL, NULL };
+    if (!scan(input, 0, &sp) || !sp.mp)
         return input;
 
-    // check that it is followed by the "mp" binary
-    char *curr = pipe+1;
-    while (*curr == ' ' || *curr == '\t')
-        curr++;
-
-    if (!strstr(curr, "mp"))
-        return input;
+    // the commands being piped into mp, mp itself, and the commands after
+    const char *up = sp.list, *up_end = sp.mp;
+    const char *mp = sp.mp+1, *mp_end = sp.after ? sp.after : mp + strlen(mp);
+    const char *down = NULL, *down_end = NULL;
+    if (sp.after) {
+        down = sp.after+1;
+        down_end = down + strlen(down);
+        trim(&down, &down_end);
+        if (down == down_end)
+            return input;
+    }
 
-    if (curr[2] != 0 && curr[2] != ' ' && curr[2] != '\n')
+    trim(&up, &up_end);
+    trim(&mp, &mp_end);
+    if (up == up_end)
         return input;
 
-    // we are now guaranteed that pipe points to the string:
-    //    | mp ...
-    // now rewrite the string without the pipe...
-
     // count the number of quotes that we need to escape
     int quotes = 0;
-    for (char *c=input; c!=pipe; c++)
+    for (const char *c=input; *c; c++)
         if (*c == '\'')
             quotes++;
 
-    char *new = malloc(strlen(input) + 7 + quotes*3);
-    curr = new;
-
-    // mp ...
-    curr = stpcpy(curr, pipe+1);
+    char *new = malloc(strlen(input) + 20 + quotes*3);
+    if (!new)
+        return input;
 
-    // mp ... -- "
-    curr[0] = ' ';
-    curr[1] = '-';
-    curr[2] = '-';
-    curr[3] = ' ';
-    curr[4] = '\'';
-    curr+=5;
+    // anything before the pipeline stays as it is
+    char *curr = stpncpy(new, input, up - input);
+    curr = stpncpy(curr, mp, mp_end - mp);
 
-    // mp ... -- 'cat /etc/passwd | grep root
-    pipe[0] = 0;
-    curr = stpcpy_escape(curr, input);
+    // mp ... --pipe 'grep ssh'
+    if (down) {
+        curr = stpcpy(curr, " --pipe ");
+        curr = stpcpy_quoted(curr, down, down_end);
+    }
 
     // mp ... -- 'cat /etc/passwd | grep root'
-    curr[0] = '\'';
-    curr[1] = 0;
+    curr = stpcpy(curr, " -- ");
+    curr = stpcpy_quoted(curr, up, up_end);
 
     free(input);
     return new;
 }
 
-// check if a path exists in the /etc/shells file
-bool check_shell(char *path) {
-    FILE *f = fopen("/etc/shells", "r");
-    bool skip_nl = false;
-    char c;
-    char *curr = path;
+// check if a path is listed in a file like /etc/shells. entries can be
+// symlinks to the path, eg. /bin/bash when /bin links to /usr/bin
+bool check_shell(const char *path, const char *shells) {
+    FILE *f = fopen(shells, "r");
+    if (!f)
+        return false;
+
+    char *line = NULL;
+    size_t cap = 0;
+    ssize_t n;
+    bool found = false;
+
+    while (!found && (n = getline(&line, &cap, f)) != -1) {
+        // trim the line, which could have windows line endings
+        char *start = line;
+        while (isspace((unsigned char)*start))
+            start++;
 
-    while ((c = fgetc(f)) != EOF) {
-        // reset on newlines
-        if (c == '\n') {
-            // if at end of path, we passed the cmp
-            if (*curr == 0)
-                return true;
+        char *end = line + n;
+        while (end > start && isspace((unsigned char)end[-1]))
+            end--;
+        *end = 0;
 
-            skip_nl = false;
-            curr = path;
+        // skip blank lines and comments
+        if (*start == 0 || *start == '#')
             continue;
+
+        if (strcmp(start, path) == 0) {
+            found = true;
+        } else {
+            char *real = realpath(start, NULL);
+            found = real && strcmp(real, path) == 0;
+            free(real);
         }
+    }
 
-        // skip until next newline
-        if (skip_nl)
-            continue;
+    free(line);
+    fclose(f);
+    return found;
+}
 
-        // skip comments
-        if (c == '#' && curr == path) {
-            skip_nl = true;
-            continue;
+// the path of the running executable, or NULL. the caller frees it
+char *exe_path() {
+    for (size_t size = 256; size <= 65536; size *= 2) {
+        char *path = malloc(size);
+        if (!path)
+            return NULL;
+
+        ssize_t n = readlink("/proc/self/exe", path, size);
+        if (n < 0) {
+            free(path);
+            return NULL;
         }
-        
-        // compare the two strings character by character
-        if (c == *curr) {
-            curr++;
+
+        // it fit with room for the terminator
+        if ((size_t)n < size) {
+            path[n] = 0;
+            return path;
         }
-        else
-            skip_nl = true;
+        free(path);
     }
 
-    return false;
+    return NULL;
+}
+
+// whether to rewrite the lines read. MP_PRELOAD_FORCE=1 or 0 overrides the
+// check for being run by a shell, which is only done once
+bool should_rewrite() {
+    const char *force = getenv("MP_PRELOAD_FORCE");
+    if (force && strcmp(force, "1") == 0)
+        return true;
+    if (force && strcmp(force, "0") == 0)
+        return false;
+
+    if (in_shell == -1) {
+        char *path = exe_path();
+        in_shell = path && check_shell(path, "/etc/shells");
+        free(path);
+    }
+
+    return in_shell;
+}
+
+// add the line as it was typed to the history, rather than leaving only
+// the rewritten one that the shell adds. the rewritten line gets a space in
+// front so that it is left out with HISTCONTROL=ignorespace, and is taken
+// back out by forget_rewritten otherwise. set MP_PRELOAD_HISTORY=0 to only
+// have the rewritten line
+char *keep_history(const char *typed, char *line) {
+    const char *keep = getenv("MP_PRELOAD_HISTORY");
+    if (keep && strcmp(keep, "0") == 0)
+        return line;
+
+    // not every program using readline has history
+    if (!og_add_history)
+        og_add_history = dlsym(RTLD_DEFAULT, "add_history");
+    if (!og_add_history)
+        return line;
+
+    char *spaced = malloc(strlen(line) + 2);
+    if (!spaced)
+        return line;
+
+    og_add_history(typed);
+    spaced[0] = ' ';
+    strcpy(spaced+1, line);
+    free(line);
+
+    free(rewritten);
+    rewritten = strdup(spaced);
+    return spaced;
+}
+
+// take the rewritten line back out of the history if the shell added it,
+// leaving only the line as typed. this is done when the next line is read,
+// by which time the shell is done with the last one
+void forget_rewritten(void) {
+    if (!rewritten)
+        return;
+
+    if (!og_history_list) {
+        og_history_length = dlsym(RTLD_DEFAULT, "history_length");
+        og_history_list = dlsym(RTLD_DEFAULT, "history_list");
+        og_remove_history = dlsym(RTLD_DEFAULT, "remove_history");
+        og_free_history_entry = dlsym(RTLD_DEFAULT, "free_history_entry");
+    }
+
+    if (og_history_length && og_history_list && og_remove_history) {
+        int n = *og_history_length;
+        hist_entry **list = og_history_list();
+        if (n > 0 && list && list[n-1] && strcmp(list[n-1]->line, rewritten) == 0) {
+            hist_entry *e = og_remove_history(n-1);
+            if (e && og_free_history_entry)
+                og_free_history_entry(e);
+        }
+    }
+
+    free(rewritten);
+    rewritten = NULL;
 }
 
 char *readline(const char *prompt) {
@@ -138,32 +393,20 @@ char *readline(const char *prompt) {
             fprintf(stderr, "Failed to find original readline\n");
             exit(1);
         }
+    }
 
-        // check if we are being executed by a shell
-        char exe_path[1024];
-        char path[1024];
-        pid_t pid = getpid();
-        sprintf(exe_path, "/proc/%d/exe", pid);
+    forget_rewritten();
 
-        size_t n = readlink(exe_path, path, 1023);
-        // ignore error case, shell is false
-        if (n > 0) {
-            path[n] = 0;
-            in_shell = check_shell(path);
-        }
-    }
-   
     char *input = og_readline(prompt);
-    if (!in_shell)
+    if (!input || !should_rewrite())
         return input;
 
-    if (!input)
-        return NULL;
-
-    return rewrite(input);
-}
+    // keep what was typed for the history, as rewrite frees it
+    char *typed = strdup(input);
+    char *line = rewrite(input);
+    if (typed && strcmp(typed, line) != 0)
+        line = keep_history(typed, line);
 
-int main() {
-    char *s = readline("bash $");
-    printf("%s\n", s);
+    free(typed);
+    return line;
 }
@@ -0,0 +1,32 @@
+// the readline shim built from preload.c, which is written out to a file so
+// that it can go in LD_PRELOAD
+
+use std::path::PathBuf;
+
+const SHIM: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/magic_pager.so"));
+
+// where the shim is kept, under $XDG_DATA_HOME or ~/.local/share
+fn dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_DATA_HOME") {
+        Some(d) if !d.is_empty() => Some(PathBuf::from(d).join("mp")),
+        _ => Some(PathBuf::from(std::env::var_os("HOME")?).join(".local/share/mp")),
+    }
+}
+
+// the path of the shim, writing it out first if it isn't there or is from
+// another version of mp
+pub fn path() -> std::io::Result<PathBuf> {
+    let dir = dir().ok_or_else(|| std::io::Error::other("HOME is not set"))?;
+    let path = dir.join("magic_pager.so");
+
+    if std::fs::read(&path).ok().as_deref() != Some(SHIM) {
+        std::fs::create_dir_all(&dir)?;
+
+        // a shell could be loading it, so swap the whole file in at once
+        let tmp = dir.join(format!(".magic_pager.so.{}", std::process::id()));
+        std::fs::write(&tmp, SHIM)?;
+        std::fs::rename(&tmp, &path)?;
+    }
+
+    Ok(path)
+}
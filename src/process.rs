@@ -0,0 +1,35 @@
+// the process groups that commands are run in, so that everything a command
+// started can be killed when it hangs or when mp exits
+
+use std::sync::Mutex;
+
+// groups that might still have processes in them
+static GROUPS: Mutex<Vec<i32>> = Mutex::new(vec![]);
+
+pub fn add(group: u32) {
+    GROUPS.lock().unwrap().push(group as i32);
+}
+
+pub fn kill(group: u32) {
+    unsafe {
+        libc::kill(-(group as i32), libc::SIGKILL);
+    }
+}
+
+// forget the groups that are empty now
+pub fn prune() {
+    GROUPS
+        .lock()
+        .unwrap()
+        .retain(|g| unsafe { libc::kill(-g, 0) } == 0);
+}
+
+// ask everything that is left to stop, eg. jobs a command put in the
+// background or a command that is stuck
+pub fn kill_all() {
+    for g in GROUPS.lock().unwrap().drain(..) {
+        unsafe {
+            libc::kill(-g, libc::SIGTERM);
+        }
+    }
+}
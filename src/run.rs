@@ -0,0 +1,166 @@
+// running a pane's command and reading its output. nothing here touches the
+// pane, so that a slow command doesn't hold up the others or the keys
+
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::buffer::{Buffer, Keep};
+use crate::line;
+use crate::process;
+
+// a command that is ready to be run
+pub struct Run {
+    pub cmd: Command,
+    // what the output is piped through, with --pipe
+    pub post: Option<Command>,
+    pub hex: bool,
+    pub max: Option<(usize, Keep)>,
+    pub timeout: Option<f64>,
+}
+
+// what came of running it
+pub struct Output {
+    pub buf: Buffer,
+    pub status: ExitStatus,
+    pub timed_out: bool,
+    // everything written to stderr
+    pub errors: Vec<u8>,
+}
+
+impl Run {
+    // run the command and read all of its output, showing what has been read
+    // so far to progress every so often
+    pub fn read(mut self, mut progress: impl FnMut(&mut Buffer)) -> Output {
+        let mut buf = Buffer::new(self.max);
+
+        // everything the command starts goes in a process group of its own,
+        // so that it can all be killed
+        let mut p = self.cmd.process_group(0).spawn().unwrap();
+        let group = p.id();
+        process::add(group);
+
+        // the command is dropped once it's spawned, so that the parent
+        // doesn't keep the pipe between them open
+        let mut post = self.post.take().map(|mut c| {
+            c.stdin(p.stdout.take().unwrap())
+                .process_group(group as i32)
+                .spawn()
+                .unwrap()
+        });
+        let stdout = match post.as_mut() {
+            Some(q) => q.stdout.take(),
+            None => p.stdout.take(),
+        };
+        let mut out = BufReader::new(stdout.unwrap());
+
+        // stderr is read as it comes so that the command never blocks on it
+        let errors = std::iter::once(&mut p)
+            .chain(post.as_mut())
+            .map(|c| collect(c.stderr.take().unwrap()))
+            .collect::<Vec<_>>();
+
+        // kill the command if it takes too long, which closes its output
+        let (done, wait) = mpsc::channel::<()>();
+        let watchdog = self.timeout.map(|t| {
+            std::thread::spawn(move || {
+                let late =
+                    wait.recv_timeout(Duration::from_secs_f64(t)) == Err(RecvTimeoutError::Timeout);
+                if late {
+                    process::kill(group);
+                }
+                late
+            })
+        });
+
+        // set when the buffer has all the lines it keeps before the end
+        let mut full = false;
+        let mut i = 0;
+        loop {
+            let mut line = vec![];
+            if self.hex {
+                let offset = buf.len() * 16;
+                match (&mut out).take(16).read_to_end(&mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => line = line::hexdump(offset, &line),
+                }
+            } else {
+                match out.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        if line.last() == Some(&b'\n') {
+                            line.pop();
+                        }
+                    }
+                }
+            }
+
+            if !buf.push(&line) {
+                full = true;
+                break;
+            }
+            i += 1;
+
+            if i == 1024 {
+                i = 0;
+                progress(&mut buf);
+            }
+        }
+        // stop the command writing if the buffer filled up before the end.
+        // that kills it with SIGPIPE, which only happened because we stopped
+        // reading so doesn't count as it failing
+        drop(out);
+        let stopped = |s: ExitStatus| {
+            let pipe = s.signal() == Some(libc::SIGPIPE) || s.code() == Some(128 + libc::SIGPIPE);
+            if full && pipe {
+                ExitStatus::from_raw(0)
+            } else {
+                s
+            }
+        };
+        let status = stopped(p.wait().unwrap());
+
+        // the command being watched failing matters more than what it is
+        // piped through
+        let status = match post.map(|mut q| stopped(q.wait().unwrap())) {
+            Some(s) if status.success() => s,
+            _ => status,
+        };
+
+        drop(done);
+        let timed_out = watchdog.is_some_and(|w| w.join().unwrap());
+        process::prune();
+
+        // jobs left in the background can keep stderr open, so stop taking
+        // it once it goes quiet
+        let mut text = vec![];
+        for rx in errors {
+            while let Ok(b) = rx.recv_timeout(Duration::from_millis(20)) {
+                text.extend(b);
+            }
+        }
+
+        Output {
+            buf,
+            status,
+            timed_out,
+            errors: text,
+        }
+    }
+}
+
+// read everything from a pipe on another thread, a chunk at a time
+fn collect(mut r: impl Read + Send + 'static) -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok(n) = r.read(&mut buf) {
+            if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
@@ -0,0 +1,280 @@
+// the pipe-to-mp trick without preloading anything, where the shell's own
+// line editor rewrites
+//   cat /etc/passwd | grep root | mp -t5
+// into
+//   mp -t5 -- 'cat /etc/passwd | grep root'
+// before running it. the rules are the same as rewrite() in preload.c
+
+const BASH: &str = r#"__mp_rewrite() {
+    if [[ $READLINE_LINE == *mp* ]]; then
+        local line
+        line=$(command mp --rewrite bash "$READLINE_LINE") && READLINE_LINE=$line
+        READLINE_POINT=${#READLINE_LINE}
+    fi
+}
+bind -x '"\C-x\C-r": __mp_rewrite'
+bind '"\C-x\C-m": accept-line'
+bind '"\C-m": "\C-x\C-r\C-x\C-m"'
+bind '"\C-j": "\C-x\C-r\C-x\C-m"'
+"#;
+
+const ZSH: &str = r#"__mp_accept_line() {
+    if [[ $BUFFER == *mp* ]]; then
+        local line
+        line=$(command mp --rewrite zsh "$BUFFER") && BUFFER=$line
+    fi
+    zle .accept-line
+}
+zle -N accept-line __mp_accept_line
+"#;
+
+const FISH: &str = r#"function __mp_execute
+    set -l line (commandline | string collect)
+    if string match -q '*mp*' -- $line
+        set -l new (command mp --rewrite fish $line | string collect)
+        and commandline -r -- $new
+    end
+    commandline -f execute
+end
+bind \r __mp_execute
+bind \n __mp_execute
+"#;
+
+// the snippet that sets up a shell, to be eval'd from its rc file
+pub fn init(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(BASH),
+        "zsh" => Some(ZSH),
+        "fish" => Some(FISH),
+        _ => None,
+    }
+}
+
+// where the last pipeline of a command line starts, the pipe into the first
+// mp in it and the pipe out of that mp
+struct Split {
+    list: usize,
+    mp: Option<usize>,
+    after: Option<usize>,
+}
+
+// whether the stage of a pipeline starting at i is the "mp" binary
+fn is_mp(s: &[u8], i: usize) -> bool {
+    let rest = &s[i..];
+    let rest = &rest[rest
+        .iter()
+        .take_while(|c| **c == b' ' || **c == b'\t')
+        .count()..];
+    rest.starts_with(b"mp") && matches!(at(rest, 2), 0 | b' ' | b'\t' | b'|')
+}
+
+// whether i is at the start of a word
+fn word_start(s: &[u8], i: usize) -> bool {
+    i == 0 || b" \t\n;&|()".contains(&s[i - 1])
+}
+
+fn at(s: &[u8], i: usize) -> u8 {
+    s.get(i).copied().unwrap_or(0)
+}
+
+// skip to after the end character, where backslashes escape it if asked
+fn skip_to(s: &[u8], mut i: usize, end: u8, escapes: bool) -> Option<usize> {
+    while i < s.len() && s[i] != end {
+        i += if escapes && s[i] == b'\\' && i + 1 < s.len() {
+            2
+        } else {
+            1
+        };
+    }
+    (i < s.len()).then_some(i + 1)
+}
+
+// skip a "..." string, which can have substitutions with their own quotes
+// inside
+fn skip_double(s: &[u8], mut i: usize, sp: &mut Split) -> Option<usize> {
+    while at(s, i) != b'"' {
+        i = match (at(s, i), at(s, i + 1)) {
+            (0, _) => return None,
+            (b'\\', c) if c != 0 => i + 2,
+            (b'$', b'(') => scan(s, i + 2, b')', sp)?,
+            (b'$', b'{') => scan(s, i + 2, b'}', sp)?,
+            (b'`', _) => skip_to(s, i + 1, b'`', true)?,
+            _ => i + 1,
+        };
+    }
+    Some(i + 1)
+}
+
+// scan a command line up to the close character, or the end when close is
+// 0. at the top level, the start of the last pipeline and the pipes around
+// mp in it are recorded. returns the index after close, or None if the line can't
+// be followed, eg. an unclosed quote, a comment or a |&
+fn scan(s: &[u8], mut i: usize, close: u8, sp: &mut Split) -> Option<usize> {
+    let top = close == 0;
+
+    loop {
+        let (c, next) = (at(s, i), at(s, i + 1));
+        if c == 0 {
+            return top.then_some(i);
+        }
+        if !top && c == close {
+            return Some(i + 1);
+        }
+
+        let prev = if i > 0 { s[i - 1] } else { 0 };
+        i = match c {
+            // a backslash at the end continues onto another line
+            b'\\' if next == 0 => return None,
+            b'\\' => i + 2,
+            b'\'' => skip_to(s, i + 1, b'\'', false)?,
+            b'"' => skip_double(s, i + 1, sp)?,
+            b'`' => skip_to(s, i + 1, b'`', true)?,
+            b'$' if next == b'\'' => skip_to(s, i + 2, b'\'', true)?,
+            b'$' if next == b'(' => scan(s, i + 2, b')', sp)?,
+            b'$' if next == b'{' => scan(s, i + 2, b'}', sp)?,
+            // subshells and process substitution
+            b'(' => scan(s, i + 1, b')', sp)?,
+            // a group of commands
+            b'{' if word_start(s, i) && (next == b' ' || next == b'\t') => {
+                scan(s, i + 1, b'}', sp)?
+            }
+            b'#' if word_start(s, i) => return None,
+            _ if !top => i + 1,
+            // the >| redirection
+            b'|' if prev == b'>' => i + 1,
+            b'|' if next == b'&' => return None,
+            b'|' if next == b'|' => {
+                sp.list = i + 2;
+                (sp.mp, sp.after) = (None, None);
+                i + 2
+            }
+            b'|' => {
+                if sp.mp.is_some() && sp.after.is_none() {
+                    sp.after = Some(i);
+                } else if sp.mp.is_none() && is_mp(s, i + 1) {
+                    sp.mp = Some(i);
+                }
+                i + 1
+            }
+            // redirections like &> and 2>&1
+            b'&' if next == b'>' || prev == b'<' || prev == b'>' => i + 1,
+            // && is two of these in a row
+            b'&' | b';' | b'\n' => {
+                sp.list = i + 1;
+                (sp.mp, sp.after) = (None, None);
+                i + 1
+            }
+            _ => i + 1,
+        };
+    }
+}
+
+// rewrite a command line whose last pipeline has mp in it to run the
+// commands before mp under mp, piping each update through the commands after
+// it, or None to leave it alone. fish needs backslashes escaped inside
+// single quotes too
+pub fn rewrite(line: &str, fish: bool) -> Option<String> {
+    let mut sp = Split {
+        list: 0,
+        mp: None,
+        after: None,
+    };
+    scan(line.as_bytes(), 0, 0, &mut sp)?;
+    let pipe = sp.mp?;
+
+    let quote = |s: &str| {
+        let s = if fish {
+            s.replace('\\', "\\\\")
+        } else {
+            s.to_string()
+        };
+        format!("'{}'", s.replace('\'', "'\\''"))
+    };
+    let blank = |c: char| c == ' ' || c == '\t';
+
+    // the commands being piped into mp, mp itself, and the commands after
+    let up = line[sp.list..pipe].trim_matches(blank);
+    let mp = line[pipe + 1..sp.after.unwrap_or(line.len())].trim_matches(blank);
+    let down = sp.after.map(|a| line[a + 1..].trim_matches(blank));
+    if up.is_empty() || down == Some("") {
+        return None;
+    }
+
+    // anything before the pipeline stays as it is
+    let start = sp.list + line[sp.list..].find(|c| !blank(c)).unwrap_or(0);
+    let mut out = format!("{}{mp}", &line[..start]);
+    if let Some(down) = down {
+        out += &format!(" --pipe {}", quote(down));
+    }
+    out += &format!(" -- {}", quote(up));
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the cases the preload shim is tested with, so that the two tokenizers
+    // can't drift apart
+    const CASES: &str = include_str!("../tests/preload_test.c");
+
+    // read a c string literal from the start of s, returning it and the rest
+    fn literal(s: &str) -> (String, &str) {
+        let mut out = String::new();
+        let mut chars = s.strip_prefix('"').unwrap().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return (out, &s[i + 2..]),
+                '\\' => out.push(match chars.next().unwrap().1 {
+                    't' => '\t',
+                    'n' => '\n',
+                    'r' => '\r',
+                    c => c,
+                }),
+                _ => out.push(c),
+            }
+        }
+        panic!("unterminated string in {s}");
+    }
+
+    fn cases() -> Vec<(String, Option<String>)> {
+        let start = CASES.find("struct test tests[] = {").unwrap();
+        let end = start + CASES[start..].find("\n};").unwrap();
+
+        CASES[start..end]
+            .lines()
+            .filter_map(|l| l.trim().strip_prefix("{ "))
+            .map(|l| {
+                let (input, rest) = literal(l);
+                let rest = rest.trim_start_matches([',', ' ']);
+                let want = (!rest.starts_with("NULL")).then(|| literal(rest).0);
+                (input, want)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_as_preload() {
+        let cases = cases();
+        assert!(cases.len() >= 56);
+        for (input, want) in cases {
+            assert_eq!(rewrite(&input, false), want, "rewriting {input:?}");
+        }
+    }
+
+    #[test]
+    fn fish() {
+        // backslashes are escapes inside fish's single quotes
+        assert_eq!(
+            rewrite("printf 'a\\n' | mp", true).as_deref(),
+            Some("mp -- 'printf '\\''a\\\\n'\\'''"),
+        );
+        assert_eq!(
+            rewrite("ls | mp | grep a\\b", true).as_deref(),
+            Some("mp --pipe 'grep a\\\\b' -- 'ls'"),
+        );
+        assert_eq!(rewrite("ls | mp", true).as_deref(), Some("mp -- 'ls'"));
+        assert_eq!(rewrite("ls", true), None);
+    }
+}
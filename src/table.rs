@@ -0,0 +1,301 @@
+// showing output as a table of aligned columns.
+//
+// lines are split into cells either as csv/tsv, or for output like `ps` and
+// `kubectl get` that lines its columns up with spaces, at the places where
+// every line has a space.
+
+use std::cmp::Ordering;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::buffer::Buffer;
+use crate::line;
+
+// what goes between the cells of a row
+const SEP: &str = " │ ";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // work it out from the output each update
+    Auto,
+    Csv,
+    Tsv,
+    Columns,
+}
+
+pub struct Table {
+    pub requested: Format,
+    format: Format,
+    // the char offset each column starts at for whitespace aligned output
+    starts: Vec<usize>,
+    // the widest cell in each column
+    widths: Vec<usize>,
+    // the column being sorted by and whether it is descending
+    pub sort: Option<(usize, bool)>,
+}
+
+impl Table {
+    pub fn new(requested: Format) -> Table {
+        Table {
+            requested,
+            format: requested,
+            starts: vec![],
+            widths: vec![],
+            sort: None,
+        }
+    }
+
+    // work out the columns again for new output
+    pub fn refresh(&mut self, buf: &Buffer) {
+        let first = if buf.is_empty() {
+            String::new()
+        } else {
+            line::normalize_cells(buf.line(0)).text
+        };
+
+        self.format = match self.requested {
+            Format::Auto => detect(&first),
+            f => f,
+        };
+
+        // columns are split where every line has a space, so that both left
+        // and right aligned columns come out whole
+        self.starts = vec![];
+        if self.format == Format::Columns {
+            let mut blank: Vec<bool> = vec![];
+            for i in 0..buf.len() {
+                let text = line::normalize_cells(buf.line(i)).text;
+                for (p, c) in text.chars().enumerate() {
+                    if p < blank.len() {
+                        blank[p] &= c.is_whitespace();
+                    } else {
+                        // shorter lines count as blank past their end
+                        blank.push(c.is_whitespace());
+                    }
+                }
+            }
+
+            for (p, b) in blank.iter().enumerate() {
+                if !b && (p == 0 || blank[p - 1]) {
+                    self.starts.push(p);
+                }
+            }
+        }
+
+        self.widths = vec![];
+        for i in 0..buf.len() {
+            let text = line::normalize_cells(buf.line(i)).text;
+            for (j, cell) in self.cells(&text).iter().enumerate() {
+                let w = cell.graphemes(true).count();
+                if j < self.widths.len() {
+                    self.widths[j] = self.widths[j].max(w);
+                } else {
+                    self.widths.push(w);
+                }
+            }
+        }
+    }
+
+    pub fn cells(&self, text: &str) -> Vec<String> {
+        match self.format {
+            Format::Csv => split_quoted(text, ','),
+            Format::Tsv => text.split('\t').map(|s| s.to_string()).collect(),
+            _ => {
+                let chars = text.chars().collect::<Vec<_>>();
+                let mut cells = vec![];
+                for (j, start) in self.starts.iter().enumerate() {
+                    let start = (*start).min(chars.len());
+                    // the last column takes the rest of the line
+                    let end = match self.starts.get(j + 1) {
+                        Some(end) => (*end).min(chars.len()),
+                        None => chars.len(),
+                    };
+                    cells.push(
+                        chars[start..end]
+                            .iter()
+                            .collect::<String>()
+                            .trim()
+                            .to_string(),
+                    );
+                }
+                cells
+            }
+        }
+    }
+
+    // the row with the cells padded out to line up
+    pub fn render(&self, text: &str) -> String {
+        let cells = self.cells(text);
+        let mut row = String::new();
+        for (j, cell) in cells.iter().enumerate() {
+            if j > 0 {
+                row.push_str(SEP);
+            }
+            // tabs are only left in to split tsv on
+            row.push_str(&cell.replace('\t', " "));
+
+            if j + 1 < cells.len() {
+                let w = cell.graphemes(true).count();
+                row.push_str(&" ".repeat(self.widths[j].saturating_sub(w)));
+            }
+        }
+        row
+    }
+
+    // the column on screen where each cell starts
+    pub fn offsets(&self) -> Vec<usize> {
+        let mut offsets = vec![];
+        let mut pos = 0;
+        for w in self.widths.iter() {
+            offsets.push(pos);
+            pos += w + SEP.chars().count();
+        }
+        offsets
+    }
+
+    // the cell that a column on screen is in
+    pub fn column_at(&self, col: usize) -> usize {
+        self.offsets().iter().rposition(|o| *o <= col).unwrap_or(0)
+    }
+
+    // compare two rows by the column being sorted on, numbers compare as
+    // numbers so that eg. restart counts sort properly
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        let Some((col, desc)) = self.sort else {
+            return Ordering::Equal;
+        };
+
+        let a = self.cells(a).get(col).cloned().unwrap_or_default();
+        let b = self.cells(b).get(col).cloned().unwrap_or_default();
+        let ord = match (a.parse::<f64>(), b.parse::<f64>()) {
+            (Ok(x), Ok(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            _ => a.cmp(&b),
+        };
+
+        if desc {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+}
+
+// guess the format from the first line
+fn detect(first: &str) -> Format {
+    if first.contains('\t') {
+        Format::Tsv
+    } else if first.contains(',') && !first.contains("  ") {
+        Format::Csv
+    } else {
+        Format::Columns
+    }
+}
+
+// split a line on a separator, where cells can be quoted with '"' and quotes
+// inside them are doubled
+fn split_quoted(text: &str, sep: char) -> Vec<String> {
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' => quoted = !quoted,
+            _ if c == sep && !quoted => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell);
+
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(format: Format, lines: &[&str]) -> Table {
+        let mut buf = Buffer::new(None);
+        for l in lines {
+            buf.push(l.as_bytes());
+        }
+        let mut t = Table::new(format);
+        t.refresh(&buf);
+        t
+    }
+
+    #[test]
+    fn detect_format() {
+        assert!(detect("a\tb\tc") == Format::Tsv);
+        assert!(detect("a,b,c") == Format::Csv);
+        assert!(detect("PID  TTY      TIME CMD") == Format::Columns);
+        // commas in aligned output don't make it csv
+        assert!(detect("NAME   PORTS        AGE") == Format::Columns);
+        assert!(detect("NAME   80,443       AGE") == Format::Columns);
+        assert!(detect("") == Format::Columns);
+    }
+
+    #[test]
+    fn quoted() {
+        assert_eq!(split_quoted("a,b,c", ','), ["a", "b", "c"]);
+        assert_eq!(split_quoted("a,,c,", ','), ["a", "", "c", ""]);
+        assert_eq!(split_quoted("\"a,b\",c", ','), ["a,b", "c"]);
+        assert_eq!(
+            split_quoted("\"say \"\"hi\"\"\",x", ','),
+            ["say \"hi\"", "x"]
+        );
+        assert_eq!(split_quoted("", ','), [""]);
+    }
+
+    #[test]
+    fn tsv() {
+        let t = table(Format::Auto, &["a\tb\tc", "1\t22\t333"]);
+        assert!(t.format == Format::Tsv);
+        assert_eq!(t.cells("1\t22\t333"), ["1", "22", "333"]);
+        assert_eq!(t.widths, [1, 2, 3]);
+    }
+
+    #[test]
+    fn csv() {
+        let t = table(Format::Auto, &["name,age", "\"Smith, J\",42"]);
+        assert!(t.format == Format::Csv);
+        assert_eq!(t.cells("\"Smith, J\",42"), ["Smith, J", "42"]);
+        assert_eq!(t.render("name,age"), "name     │ age");
+    }
+
+    #[test]
+    fn columns() {
+        let t = table(
+            Format::Auto,
+            &[
+                "  PID TTY      CMD",
+                "    1 ?        init",
+                "12345 pts/0    bash",
+            ],
+        );
+        assert!(t.format == Format::Columns);
+        assert_eq!(t.cells("    1 ?        init"), ["1", "?", "init"]);
+        // the last column takes the rest of the line
+        assert_eq!(
+            t.cells("   42 pts/1    vim a b"),
+            ["42", "pts/1", "vim a b"]
+        );
+        assert_eq!(t.offsets(), [0, 8, 16]);
+        assert_eq!(t.column_at(9), 1);
+    }
+
+    #[test]
+    fn sorting() {
+        let mut t = table(Format::Csv, &["n,x", "10,a", "9,b"]);
+        t.sort = Some((0, false));
+        // numbers compare as numbers
+        assert!(t.compare("9,b", "10,a") == Ordering::Less);
+        t.sort = Some((1, true));
+        assert!(t.compare("9,b", "10,a") == Ordering::Less);
+    }
+}
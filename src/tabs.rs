@@ -0,0 +1,264 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use crossterm::{cursor, style, terminal, ExecutableCommand, QueueableCommand};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::layout::Layout;
+use crate::ui::State;
+
+// the tabs that are open, each holding its own layout of panes
+pub struct Tabs {
+    pub tabs: Vec<Layout>,
+    pub current: usize,
+    // the text typed after ':' while the prompt is open
+    prompt: Option<String>,
+    // shown on the bottom line until the next key, eg. errors from the prompt
+    message: Option<String>,
+    // panes that have been opened and need their triggers started
+    pub started: Vec<usize>,
+}
+
+impl Tabs {
+    pub fn init(mut panes: Vec<State>) -> Tabs {
+        // batch mode prints the output and leaves the terminal alone
+        let batch = panes[0].opts.batch;
+        if batch {
+            for p in panes.iter_mut() {
+                p.visible = false;
+            }
+        } else {
+            std::io::stdout()
+                .execute(terminal::EnterAlternateScreen)
+                .unwrap();
+            terminal::enable_raw_mode().unwrap();
+        }
+
+        let started = panes.iter().map(|p| p.id).collect();
+
+        // in tab mode each command gets its own tab instead of a pane
+        let tabs = if panes[0].opts.tabs {
+            panes.into_iter().map(|p| Layout::new(vec![p])).collect()
+        } else {
+            vec![Layout::new(panes)]
+        };
+
+        let mut me = Tabs {
+            tabs,
+            current: 0,
+            prompt: None,
+            message: None,
+            started,
+        };
+
+        // the panes are read into once their triggers start
+        if !batch {
+            me.resize();
+            me.draw();
+        }
+        me
+    }
+
+    pub fn batch(&self) -> bool {
+        self.tabs[0].panes[0].opts.batch
+    }
+
+    // find a pane in any of the tabs
+    pub fn pane(&mut self, id: usize) -> Option<&mut State> {
+        self.tabs
+            .iter_mut()
+            .flat_map(|l| l.panes.iter_mut())
+            .find(|p| p.id == id)
+    }
+
+    fn show_bar(&self) -> bool {
+        self.tabs.len() > 1 || self.tabs[0].panes[0].opts.tabs
+    }
+
+    fn resize(&mut self) {
+        let top = if self.show_bar() { 1 } else { 0 };
+        for (i, l) in self.tabs.iter_mut().enumerate() {
+            l.top = top;
+            l.resize();
+            for p in l.panes.iter_mut() {
+                p.visible &= i == self.current;
+            }
+        }
+    }
+
+    // open a tab for a file or a command, which takes the options of the
+    // pane that is focused. what the focused pane's output is piped through,
+    // waited for or handed to is about that output, so isn't taken
+    fn open(&mut self, file: Option<PathBuf>, cmd: Option<String>) {
+        let curr = &self.tabs[self.current];
+        let mut opts = curr.panes[curr.focus].opts.clone();
+        opts.files.retain(|f| Some(f) != opts.file.as_ref());
+        opts.tail = opts.tail && file.is_some();
+        opts.output = None;
+        opts.pipe = None;
+        opts.until = None;
+        opts.alert = None;
+        opts.alert_cmd = None;
+        opts.on_update = None;
+        opts.on_success = None;
+        opts.on_fail = None;
+
+        if let Some(f) = file.as_ref() {
+            if !f.exists() {
+                self.message = Some(format!("file '{}' does not exist", f.to_string_lossy()));
+                return;
+            }
+            if !opts.never {
+                opts.files.push(f.clone());
+            }
+        }
+        opts.file = file;
+        opts.cmd = cmd;
+
+        let pane = State::new(opts);
+        self.started.push(pane.id);
+
+        self.tabs.push(Layout::new(vec![pane]));
+        self.current = self.tabs.len() - 1;
+    }
+
+    fn close(&mut self) {
+        if self.tabs.len() == 1 {
+            self.tabs[0].panes[0].exit();
+        }
+
+        self.tabs.remove(self.current);
+        self.current = self.current.min(self.tabs.len() - 1);
+    }
+
+    // run the command typed into the prompt
+    fn run(&mut self, line: &str) {
+        // :!cmd opens a command
+        if let Some(c) = line.strip_prefix('!') {
+            if !c.trim().is_empty() {
+                self.open(None, Some(c.trim().into()));
+            }
+            return;
+        }
+
+        let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+
+        match cmd {
+            "e" | "edit" if !arg.is_empty() => self.open(Some(arg.into()), None),
+            "q" | "close" => self.close(),
+            // write the focused pane's output, wr keeps it exactly as read
+            "w" | "wr" if !arg.is_empty() => {
+                let l = &self.tabs[self.current];
+                self.message = Some(match l.panes[l.focus].write(arg.as_ref(), cmd == "wr") {
+                    Ok(n) => format!("wrote {n} lines to {arg}"),
+                    Err(e) => format!("{arg}: {e}"),
+                });
+            }
+            "" => {}
+            _ => self.message = Some(format!("unknown command: {line}")),
+        }
+    }
+
+    pub fn event(&mut self, c: char) {
+        self.message = None;
+
+        if let Some(prompt) = self.prompt.as_mut() {
+            match c {
+                '\r' | '\n' => {
+                    let line = self.prompt.take().unwrap();
+                    self.run(&line);
+                }
+                '\x1b' | '\x03' => self.prompt = None,
+                // backspacing past the start closes the prompt
+                '\x7f' | '\x08' if prompt.is_empty() => self.prompt = None,
+                '\x7f' | '\x08' => {
+                    prompt.pop();
+                }
+                _ if !c.is_control() => prompt.push(c),
+                _ => {}
+            }
+            self.resize();
+            return;
+        }
+
+        match c {
+            // a filter being typed can have any of these in it
+            _ if self.tabs[self.current].typing() => self.tabs[self.current].event(c),
+            ':' => self.prompt = Some(String::new()),
+            // ^N/^P
+            '\x0e' => self.current = (self.current + 1) % self.tabs.len(),
+            '\x10' => self.current = (self.current + self.tabs.len() - 1) % self.tabs.len(),
+            // ^X
+            '\x18' => self.close(),
+            _ => self.tabs[self.current].event(c),
+        }
+        self.resize();
+    }
+
+    pub fn draw(&mut self) {
+        if self.batch() {
+            return;
+        }
+
+        self.resize();
+        let mut stdout = std::io::stdout();
+        let (w, h) = terminal::size().unwrap();
+
+        if self.show_bar() {
+            stdout.queue(cursor::MoveTo(0, 0)).unwrap();
+            let mut used = 0;
+            for (i, l) in self.tabs.iter().enumerate() {
+                let label = format!(" {} {} ", i + 1, l.panes[l.focus].title());
+                let label = UnicodeSegmentation::graphemes(label.as_str(), true)
+                    .take((w as usize).saturating_sub(used))
+                    .collect::<String>();
+                used += label.graphemes(true).count();
+
+                if i == self.current {
+                    stdout
+                        .queue(style::SetAttribute(style::Attribute::Reverse))
+                        .unwrap();
+                }
+                print!("{label}");
+                stdout
+                    .queue(style::SetAttribute(style::Attribute::Reset))
+                    .unwrap();
+            }
+            print!("{:1$}", "", (w as usize).saturating_sub(used));
+        }
+
+        self.tabs[self.current].draw();
+
+        // the prompt covers the bottom line
+        let bottom = if let Some(p) = self.prompt.as_ref() {
+            Some(format!(":{p}"))
+        } else {
+            self.message.clone()
+        };
+
+        if let Some(s) = bottom {
+            let s = UnicodeSegmentation::graphemes(s.as_str(), true)
+                .take(w as usize)
+                .collect::<String>();
+            let n = s.graphemes(true).count();
+
+            stdout
+                .queue(cursor::SavePosition)
+                .unwrap()
+                .queue(cursor::MoveTo(0, h.saturating_sub(1)))
+                .unwrap();
+            print!("{s}{:1$}", "", (w as usize).saturating_sub(n));
+
+            // leave the cursor at the end of the prompt, or back in the pane
+            if self.prompt.is_some() {
+                stdout
+                    .queue(cursor::MoveTo(n as u16, h.saturating_sub(1)))
+                    .unwrap();
+            } else {
+                stdout.queue(cursor::RestorePosition).unwrap();
+            }
+        }
+        stdout.flush().unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+// the threads that update panes when something happens
+
+use std::sync::{Arc, Mutex};
+
+use inotify::Inotify;
+
+use crate::tabs::Tabs;
+
+// what caused an update
+#[derive(Clone, Copy)]
+pub enum Trigger {
+    // the first read, when the pane is opened
+    Start,
+    Timer,
+    File,
+}
+
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Trigger::Start => write!(f, "start"),
+            Trigger::Timer => write!(f, "timer"),
+            Trigger::File => write!(f, "file"),
+        }
+    }
+}
+
+// start a thread for each watching operation of a pane. they stop once the
+// pane has been closed
+pub fn spawn(s: Arc<Mutex<Tabs>>, id: usize) {
+    let Some((opts, fresh)) = s
+        .lock()
+        .unwrap()
+        .pane(id)
+        .map(|p| (p.opts.clone(), p.iteration == 0))
+    else {
+        return;
+    };
+
+    // the first read is done here rather than when the pane is opened, so
+    // that a command that hangs doesn't stop keys being read
+    if fresh {
+        let s = s.clone();
+        std::thread::spawn(move || {
+            update(&s, id, Trigger::Start);
+        });
+    }
+
+    if let Some(t) = opts.time {
+        let s = s.clone();
+        std::thread::Builder::new()
+            .name("timer".to_string())
+            .spawn(move || {
+                timer_thread(s, id, t);
+            })
+            .unwrap();
+    }
+
+    if !opts.files.is_empty() {
+        let s = s.clone();
+        std::thread::spawn(move || {
+            inotify_thread(s, id);
+        });
+    }
+}
+
+// update a pane and redraw, returning false if the pane is gone. the lock
+// is let go while the command runs, so that the other panes and the keys
+// carry on
+pub fn update(s: &Arc<Mutex<Tabs>>, id: usize, trigger: Trigger) -> bool {
+    let Some(busy) = s.lock().unwrap().pane(id).map(|p| p.busy.clone()) else {
+        return false;
+    };
+    let _busy = busy.lock().unwrap();
+
+    let Some(run) = s.lock().unwrap().pane(id).map(|p| p.begin(trigger)) else {
+        return false;
+    };
+    let out = run.map(|r| {
+        r.read(|buf| {
+            if let Some(p) = s.lock().unwrap().pane(id) {
+                p.draw_partial(buf);
+            }
+        })
+    });
+
+    let mut tabs = s.lock().unwrap();
+    let Some(pane) = tabs.pane(id) else {
+        return false;
+    };
+    pane.finish(trigger, out);
+    tabs.draw();
+    true
+}
+
+fn timer_thread(s: Arc<Mutex<Tabs>>, id: usize, t: f64) {
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs_f64(t));
+        if !update(&s, id, Trigger::Timer) {
+            return;
+        }
+    }
+}
+
+fn inotify_thread(s: Arc<Mutex<Tabs>>, id: usize) {
+    let mut inotify = Inotify::init().unwrap();
+    // files that are moved or deleted are watched again once they come back,
+    // which is what happens when logs are rotated
+    let mask = inotify::WatchMask::MODIFY
+        | inotify::WatchMask::MOVE_SELF
+        | inotify::WatchMask::DELETE_SELF;
+
+    // add the files to be watched
+    let mut watches = vec![];
+    {
+        let mut tabs = s.lock().unwrap();
+        let Some(pane) = tabs.pane(id) else {
+            return;
+        };
+        for f in pane.opts.files.iter() {
+            let wd = inotify.watches().add(f, mask).unwrap();
+            watches.push((wd, f.clone()));
+        }
+    }
+
+    // wait for changes
+    let mut buffer = [0; 1024];
+    loop {
+        let mut gone = vec![];
+        for e in inotify.read_events_blocking(&mut buffer).unwrap() {
+            if e.mask
+                .intersects(inotify::EventMask::MOVE_SELF | inotify::EventMask::DELETE_SELF)
+            {
+                gone.push(e.wd.clone());
+            }
+        }
+
+        for wd in gone {
+            let Some(i) = watches.iter().position(|(w, _)| *w == wd) else {
+                continue;
+            };
+            let (_, f) = watches.remove(i);
+            let _ = inotify.watches().remove(wd);
+
+            // wait for the replacement to show up
+            let wd = loop {
+                match inotify.watches().add(&f, mask) {
+                    Ok(wd) => break wd,
+                    Err(_) => std::thread::sleep(std::time::Duration::from_millis(100)),
+                }
+            };
+            watches.push((wd, f));
+        }
+
+        if !update(&s, id, Trigger::File) {
+            return;
+        }
+    }
+}
@@ -1,12 +1,41 @@
 extern crate unicode_segmentation;
 
-use std::io::{BufRead, BufReader, Write};
-use std::process::Command;
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crossterm::{cursor, style, terminal, ExecutableCommand, QueueableCommand};
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::batch;
+use crate::buffer::Buffer;
+use crate::clipboard;
+use crate::diff;
+use crate::filter::{self, Filter};
+use crate::hooks;
+use crate::line::{self, Attr, Line};
+use crate::notify;
 use crate::opts::Options;
+use crate::process;
+use crate::run::{Output, Run};
+use crate::table::{Format, Table};
+use crate::triggers::Trigger;
+use crate::until::Until;
+
+// how a visual selection extends from where it was started
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Select {
+    Char,
+    Line,
+}
 
 #[derive(PartialEq, Eq)]
 enum Action {
@@ -16,6 +45,14 @@ enum Action {
     Left(usize),
     Right(usize),
     Jump(usize, usize),
+    Filter(String),
+    Header(usize, usize),
+    Table,
+    Sort,
+    Select(Select),
+    Yank,
+    YankMatch,
+    Errors,
     Cont,
 }
 
@@ -24,80 +61,590 @@ enum Mode {
     Esc,
     Csi(String),
     Goto(String),
+    Filter(String),
 }
 
-impl Mode {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Mode::Normal => "    ".into(),
-            Mode::Esc => "ESC ".into(),
-            Mode::Csi(s) => format!("CSI {s}"),
-            Mode::Goto(s) => format!("g{s:3}"),
+            Mode::Normal => write!(f, "    "),
+            Mode::Esc => write!(f, "ESC "),
+            Mode::Csi(s) => write!(f, "CSI {s}"),
+            Mode::Goto(s) => write!(f, "g{s:3}"),
+            Mode::Filter(s) => write!(f, "&{s}"),
         }
     }
 }
 
+// used to give each pane an id that stays the same as panes come and go
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub struct State {
+    pub id: usize,
     pub cmd: Command,
-    pub buf: Vec<String>,
+    // one update runs at a time, without holding the lock on the panes
+    pub busy: Arc<Mutex<()>>,
+    // when the update that is running started
+    started: Instant,
+    // how the command exited the last time it ran
+    pub status: Option<ExitStatus>,
+    // raw bytes of each line without the newline, or rows of the hex dump
+    pub buf: Buffer,
 
     // position of the cursor in the terminal window
     pub cursor: (u16, u16),
-    // size of the terminal
-    pub term_size: (u16, u16),
+    // where our pane starts on the terminal and how big it is
+    pub origin: (u16, u16),
+    pub size: (u16, u16),
+    // whether keys go to this pane
+    pub focused: bool,
+    // whether the pane is on screen, panes in other tabs aren't
+    pub visible: bool,
     // how much weve scrolled through the buffer
     pub scroll: (usize, usize),
     mode: Mode,
     pub opts: Options,
+    // shown in the status bar until the next key
+    message: Option<String>,
+
+    // the filters that are applied, and the lines in the buffer that get
+    // through them. all lines are shown when there are none
+    filters: Vec<Filter>,
+    rows: Option<Vec<usize>>,
+
+    // the number of lines and columns frozen at the top and left
+    pub header: (usize, usize),
+    // set when showing the output as a table
+    pub table: Option<Table>,
+    // the kind of visual selection and the row and column it started at
+    select: Option<(Select, usize, usize)>,
+
+    // in tail mode, the inode of the file and the offset of the end of the
+    // last complete line read from it
+    tail: Option<(u64, u64)>,
+    // whether the last line in the buffer had no newline yet
+    partial: bool,
+    // in batch mode, a hash of the output last printed
+    printed: Option<u64>,
+    // with --until-change, a hash of the output the first time it was read
+    first: Option<u64>,
+    // why the last run failed, when the output from the one before was put
+    // back in place of its own
+    failed: Option<String>,
+    // whether there was any output from before to put back
+    kept: bool,
+    // what the failed run printed, which the hooks are given
+    rejected: Option<Buffer>,
+    // what the last run wrote to stderr, swapped with the output by E along
+    // with where we were in it
+    errors: Buffer,
+    showing_errors: bool,
+    behind: ((usize, usize), (u16, u16)),
+    // how many times the pane has been updated
+    pub iteration: u64,
+    // with --alert, the text of the output the last time it was read
+    last: Option<Vec<String>>,
 }
 
 impl State {
-    pub fn init(cmd: Command, opts: Options) -> State {
-        std::io::stdout()
-            .execute(terminal::EnterAlternateScreen)
-            .unwrap();
-        terminal::enable_raw_mode().unwrap();
-
-        let mut me = State {
-            cmd,
-            buf: vec![],
+    // create a pane, which is sized and filled in by its layout
+    pub fn new(opts: Options) -> State {
+        State {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            cmd: command(&opts),
+            busy: Arc::new(Mutex::new(())),
+            started: Instant::now(),
+            status: None,
+            buf: Buffer::new(opts.max_lines),
+            header: opts.header,
+            table: opts.table.map(Table::new),
+            select: None,
             cursor: (0, 0),
-            term_size: (0, 0),
+            origin: (0, 0),
+            size: terminal::size().unwrap_or((80, 24)),
+            focused: true,
+            visible: true,
             scroll: (0, 0),
             mode: Mode::Normal,
             opts,
+            message: None,
+            filters: vec![],
+            rows: None,
+            tail: None,
+            partial: false,
+            printed: None,
+            first: None,
+            failed: None,
+            kept: false,
+            rejected: None,
+            errors: Buffer::new(None),
+            showing_errors: false,
+            behind: ((0, 0), (0, 0)),
+            iteration: 0,
+            last: None,
+        }
+    }
+
+    // update in one go, without any other panes to get out of the way of
+    pub fn update(&mut self, trigger: Trigger) {
+        let out = self.begin(trigger).map(|r| r.read(|_| {}));
+        self.finish(trigger, out);
+    }
+
+    // start an update. files are read straight away, while a command is
+    // handed back to be run without holding up everything else, and what it
+    // read is passed to finish
+    pub fn begin(&mut self, trigger: Trigger) -> Option<Run> {
+        self.iteration += 1;
+        self.started = Instant::now();
+
+        if self.showing_errors {
+            self.toggle_errors();
+        }
+
+        if self.opts.tail {
+            self.update_tail();
+            return None;
+        }
+
+        // files are read in directly rather than through cat
+        if let (Some(f), false, None) = (
+            self.opts.file.as_ref(),
+            self.opts.hex,
+            self.opts.pipe.as_ref(),
+        ) {
+            if let Ok(buf) = Buffer::read(f, self.opts.max_lines) {
+                let anchor = self.anchor();
+                let old = std::mem::replace(&mut self.buf, buf);
+                self.refilter();
+                if let Some(line) = anchor {
+                    self.follow(&old, line);
+                }
+                return None;
+            }
+        }
+
+        // let the command know how it came to be run
+        let last = self
+            .status
+            .and_then(|s| s.code().or(s.signal().map(|n| 128 + n)));
+        let mut cmd = command(&self.opts);
+        let mut post = self.opts.pipe.as_ref().map(|p| shell(&self.opts, p));
+        for c in std::iter::once(&mut cmd).chain(post.as_mut()) {
+            c.env("MP_ITERATION", self.iteration.to_string())
+                .env("MP_TRIGGER", trigger.to_string())
+                .env(
+                    "MP_LAST_STATUS",
+                    last.map(|c| c.to_string()).unwrap_or_default(),
+                );
+        }
+
+        Some(Run {
+            cmd,
+            post,
+            hex: self.opts.hex,
+            max: self.opts.max_lines,
+            timeout: self.opts.timeout,
+        })
+    }
+
+    // draw output that is still being read, swapped in just for drawing. the
+    // filtered rows and table columns are still those of the old output, so
+    // those wait for the end
+    pub fn draw_partial(&mut self, buf: &mut Buffer) {
+        if !self.visible || self.rows.is_some() || self.table.is_some() || self.showing_errors {
+            return;
+        }
+
+        std::mem::swap(&mut self.buf, buf);
+        self.draw();
+        std::mem::swap(&mut self.buf, buf);
+    }
+
+    // take in what the command read, if it was run, and do everything that
+    // follows an update
+    pub fn finish(&mut self, trigger: Trigger, out: Option<Output>) {
+        if let Some(out) = out {
+            // E may have been pressed while the command was running
+            if self.showing_errors {
+                self.toggle_errors();
+            }
+            self.take(out);
+        }
+        let duration = self.started.elapsed();
+
+        if let Some(path) = self.opts.output.clone() {
+            if let Err(e) = self.write(&path, self.opts.raw) {
+                self.message = Some(format!("{}: {e}", path.to_string_lossy()));
+            }
+        }
+
+        if self.opts.batch {
+            self.print();
+        }
+
+        self.run_hooks(trigger, duration);
+
+        // the first read is what later ones are compared to
+        if let Some(alert) = self.opts.alert.clone() {
+            let text = self.text();
+            if let Some(last) = self.last.replace(text.clone()) {
+                if let Some(reason) = alert.fired(&last, &text) {
+                    self.alert(&reason);
+                    if let Some(cmd) = self.opts.alert_cmd.as_ref() {
+                        let env = [("MP_TITLE", self.title()), ("MP_REASON", reason)];
+                        let diff = diff::lines(&last, &text).into_bytes();
+                        hooks::run(cmd, diff, &env, !self.opts.batch);
+                    }
+                }
+            }
+        }
+
+        if let Some(until) = self.opts.until.clone() {
+            let changed = match until {
+                Until::Change => {
+                    let hash = self.hash();
+                    *self.first.get_or_insert(hash) != hash
+                }
+                _ => false,
+            };
+
+            if let Some(reason) = until.met(&self.buf, self.status, changed) {
+                self.done(&reason);
+            }
+        }
+    }
+
+    // run the hooks for an update, which get the output on stdin
+    fn run_hooks(&self, trigger: Trigger, duration: Duration) {
+        let ok = self.status.map(|s| s.success());
+        let cmds = [
+            self.opts.on_update.as_ref(),
+            self.opts.on_success.as_ref().filter(|_| ok == Some(true)),
+            self.opts.on_fail.as_ref().filter(|_| ok == Some(false)),
+        ];
+        let cmds = cmds.into_iter().flatten().collect::<Vec<_>>();
+        if cmds.is_empty() {
+            return;
+        }
+
+        // a failed run is what the hooks are about, even if its output wasn't
+        // kept
+        let mut out = vec![];
+        let buf = self.rejected.as_ref().unwrap_or(&self.buf);
+        let _ = write_lines(buf, &mut out, self.opts.raw);
+        let code = self.status.and_then(|s| s.code());
+        let env = [
+            ("MP_TITLE", self.title()),
+            ("MP_TRIGGER", trigger.to_string()),
+            (
+                "MP_EXIT_CODE",
+                code.map(|c| c.to_string()).unwrap_or_default(),
+            ),
+            ("MP_DURATION", format!("{:.3}", duration.as_secs_f64())),
+        ];
+        for cmd in cmds {
+            hooks::run(cmd, out.clone(), &env, !self.opts.batch);
+        }
+    }
+
+    // the lines of the output as they are shown
+    fn text(&self) -> Vec<String> {
+        (0..self.buf.len())
+            .map(|i| line::normalize(self.buf.line(i)).text)
+            .collect()
+    }
+
+    // a hash of the output, to tell whether it changed
+    fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for i in 0..self.buf.len() {
+            self.buf.line(i).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    // print the output to stdout under a separator line, in batch mode
+    fn print(&mut self) {
+        let hash = self.hash();
+
+        if self.opts.changes && self.printed == Some(hash) {
+            return;
+        }
+        self.printed = Some(hash);
+
+        let mut stdout = std::io::stdout().lock();
+        let status = match self.failed.as_ref() {
+            Some(f) if self.kept => format!("{f}, last output kept"),
+            Some(f) => f.clone(),
+            None => batch::describe(self.status),
+        };
+        let res = writeln!(stdout, "{}", batch::separator(&self.title(), &status))
+            .and_then(|_| write_lines(&self.buf, &mut stdout, self.opts.raw))
+            .and_then(|_| stdout.flush());
+
+        // nobody is reading any more
+        if res.is_err() {
+            std::process::exit(1);
+        }
+    }
+
+    // write the whole buffer to a file, either as plain text or exactly as
+    // it was read with any colours left in
+    pub fn write(&self, path: &Path, raw: bool) -> std::io::Result<usize> {
+        let mut f = std::io::BufWriter::new(File::create(path)?);
+        let n = write_lines(&self.buf, &mut f, raw)?;
+        f.flush()?;
+        Ok(n)
+    }
+
+    // take in what a run of the command read. the old output is kept if
+    // the command failed
+    fn take(&mut self, out: Output) {
+        let anchor = self.anchor();
+        let mut old = std::mem::replace(&mut self.buf, out.buf);
+        self.status = Some(out.status);
+
+        let text = out.errors;
+        self.errors = Buffer::new(None);
+        if !text.is_empty() {
+            for l in text
+                .strip_suffix(b"\n")
+                .unwrap_or(&text)
+                .split(|b| *b == b'\n')
+            {
+                self.errors.push(l);
+            }
+        }
+
+        let failed = if out.timed_out {
+            Some(format!("timed out after {}s", self.opts.timeout.unwrap()))
+        } else if !out.status.success() {
+            Some(batch::describe(self.status))
+        } else if self.buf.is_empty() && !old.is_empty() {
+            Some("no output".into())
+        } else {
+            None
+        };
+
+        // whatever a command that timed out printed is cut short, and with
+        // --keep a failed run doesn't replace the last output
+        self.failed = None;
+        self.rejected = None;
+        if let Some(f) = failed {
+            if out.timed_out || (self.opts.keep && !old.is_empty()) {
+                std::mem::swap(&mut self.buf, &mut old);
+                self.failed = Some(f);
+                self.kept = !self.buf.is_empty();
+            }
+        }
+
+        self.refilter();
+        if self.failed.is_some() {
+            self.rejected = Some(old);
+        } else if let Some(line) = anchor {
+            self.follow(&old, line);
+        }
+    }
+
+    // the line under the cursor, which is followed to wherever it goes.
+    // rows of a hex dump all move when anything is added so they aren't
+    fn anchor(&self) -> Option<usize> {
+        let row = self.scroll.1 + self.cursor.1 as usize;
+        (row < self.rows() && !self.opts.hex).then(|| self.line_of(row))
+    }
+
+    // put the line that was under the cursor back under it, so that lines
+    // added or removed above it don't move what is being read
+    fn follow(&mut self, old: &Buffer, line: usize) {
+        let buf = &self.buf;
+        let line = diff::follow(old.len(), buf.len(), line, |i, j| {
+            old.line(i) == buf.line(j)
+        });
+        let row = match self.rows.as_ref() {
+            // sorted rows aren't in order of their lines
+            Some(rows) => rows
+                .iter()
+                .position(|l| *l == line)
+                .unwrap_or_else(|| self.row_of(line)),
+            None => line,
+        };
+
+        match row.checked_sub(self.cursor.1 as usize) {
+            Some(scroll) => self.scroll.1 = scroll,
+            None => (self.scroll.1, self.cursor.1) = (0, row as u16),
+        }
+        self.clamp();
+    }
+
+    // read only the lines appended to the file since the last update,
+    // starting again if the file was truncated or replaced
+    fn update_tail(&mut self) {
+        let path = self.opts.file.clone().unwrap();
+        let Ok(mut f) = File::open(&path) else {
+            // the file is being rotated, keep what we have
+            return;
+        };
+        let meta = f.metadata().unwrap();
+
+        let at_end =
+            self.tail.is_none() || self.scroll.1 + self.cursor.1 as usize + 1 >= self.rows();
+
+        let offset = match self.tail {
+            Some((ino, offset)) if ino == meta.ino() && offset <= meta.len() => offset,
+            _ => {
+                self.buf.clear();
+                self.partial = false;
+                0
+            }
+        };
+
+        if self.partial {
+            self.buf.pop();
+            self.partial = false;
+        }
+
+        f.seek(SeekFrom::Start(offset)).unwrap();
+        let mut out = BufReader::new(f);
+        let mut offset = offset;
+        loop {
+            let mut line = vec![];
+            match out.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if line.last() == Some(&b'\n') {
+                        line.pop();
+                        offset += n as u64;
+                    } else {
+                        // read this again next time in case it grew
+                        self.partial = true;
+                    }
+                }
+            }
+
+            if !self.buf.push(&line) {
+                break;
+            }
+        }
+        self.tail = Some((meta.ino(), offset));
+
+        // follow the end of the file if that's where we were
+        self.refilter();
+        if at_end {
+            self.jump(self.rows(), 0);
+        }
+    }
+
+    // the number of rows that can be shown, fewer than the lines in the
+    // buffer when filtering
+    fn rows(&self) -> usize {
+        match self.rows.as_ref() {
+            Some(rows) => rows.len(),
+            None => self.buf.len(),
+        }
+    }
+
+    // the line in the buffer that is shown on a row
+    fn line_of(&self, row: usize) -> usize {
+        match self.rows.as_ref() {
+            Some(rows) => rows[row],
+            None => row,
+        }
+    }
+
+    // the first row showing the line or one after it
+    fn row_of(&self, line: usize) -> usize {
+        match self.rows.as_ref() {
+            Some(rows) => rows.partition_point(|l| *l < line),
+            None => line,
+        }
+    }
+
+    // work out which lines get through the filters again
+    fn refilter(&mut self) {
+        if let Some(t) = self.table.as_mut() {
+            t.refresh(&self.buf);
+        }
+
+        self.rows = if self.filters.is_empty() {
+            None
+        } else {
+            Some(filter::apply(&self.filters, &self.buf, self.header.0))
         };
 
-        me.update();
-        me.draw();
-        me
+        // sorting a table reorders the rows below its header
+        if let Some(t) = self.table.as_ref().filter(|t| t.sort.is_some()) {
+            let mut rows = self
+                .rows
+                .take()
+                .unwrap_or_else(|| (0..self.buf.len()).collect());
+
+            let fixed = self.header.0.max(1);
+            let start = rows.partition_point(|l| *l < fixed);
+            let mut keyed = rows[start..]
+                .iter()
+                .map(|l| (*l, line::normalize_cells(self.buf.line(*l)).text))
+                .collect::<Vec<_>>();
+            keyed.sort_by(|a, b| t.compare(&a.1, &b.1));
+
+            rows.truncate(start);
+            rows.extend(keyed.into_iter().map(|(l, _)| l));
+            self.rows = Some(rows);
+        }
+
+        self.clamp();
     }
 
-    pub fn update(&mut self) {
-        // execute command, parse lines, store in buffer
-        self.buf.clear();
-        let mut p = self.cmd.spawn().unwrap();
-        let mut lines = BufReader::new(p.stdout.take().unwrap()).lines();
+    // the graphemes of a row that are in the visual selection
+    fn selected(&self, r: usize) -> Range<usize> {
+        let Some((kind, row, col)) = self.select else {
+            return 0..0;
+        };
+
+        let cur = (
+            self.scroll.1 + self.cursor.1 as usize,
+            self.scroll.0 + self.cursor.0 as usize,
+        );
+        let (start, end) = if (row, col) <= cur {
+            ((row, col), cur)
+        } else {
+            (cur, (row, col))
+        };
 
-        let mut i = 0;
-        while let Some(Ok(line)) = lines.next() {
-            self.buf.push(line);
-            i += 1;
+        if r < start.0 || r > end.0 {
+            return 0..0;
+        }
 
-            if i == 1024 {
-                i = 0;
-                self.draw();
+        match kind {
+            Select::Line => 0..usize::MAX,
+            Select::Char => {
+                let from = if r == start.0 { start.1 } else { 0 };
+                let to = if r == end.0 { end.1 + 1 } else { usize::MAX };
+                from..to
             }
         }
+    }
 
-        // ensure that the scroll position/cursor is within the text
-        while self.scroll.1 + self.cursor.1 as usize > self.buf.len().saturating_sub(1) {
+    // the line as it is shown, laid out in cells for a table
+    fn display(&self, i: usize) -> Line {
+        match self.table.as_ref() {
+            Some(t) => Line {
+                text: t.render(&line::normalize_cells(self.buf.line(i)).text),
+                spans: vec![],
+            },
+            None => line::normalize(self.buf.line(i)),
+        }
+    }
+
+    // ensure that the scroll position/cursor is within the text
+    fn clamp(&mut self) {
+        while self.scroll.1 + self.cursor.1 as usize > self.rows().saturating_sub(1) {
             self.up();
         }
     }
 
     fn left(&mut self) {
-        if self.cursor.0 == 0 && self.scroll.0 > 0 {
+        if self.cursor.0 as usize <= self.header.1 && self.scroll.0 > 0 {
             self.scroll.0 = self.scroll.0.saturating_sub(1);
         } else {
             self.cursor.0 = self.cursor.0.saturating_sub(1);
@@ -105,21 +652,22 @@ impl State {
     }
 
     fn right(&mut self) {
-        let num_digs = if self.buf.len() == 0 {
+        let num_digs = if self.buf.is_empty() {
             0
         } else {
             self.buf.len().ilog10()
         } + 1;
 
-        if self.cursor.0 == self.term_size.0 - num_digs as u16 - 3 {
+        if self.cursor.0 >= self.size.0.saturating_sub(num_digs as u16 + 3) {
             self.scroll.0 += 1;
         } else {
-            self.cursor.0 = (self.cursor.0 + 1).min(self.term_size.0 - 1);
+            self.cursor.0 = (self.cursor.0 + 1).min(self.size.0.saturating_sub(1));
         }
     }
 
     fn up(&mut self) {
-        let vscroll = self.term_size.1 / 5;
+        // the cursor stays below the header once we've scrolled
+        let vscroll = (self.size.1 / 5).max(self.header.0 as u16);
         if self.cursor.1 <= vscroll && self.scroll.1 > 0 {
             self.scroll.1 = self.scroll.1.saturating_sub(1);
         } else {
@@ -128,17 +676,19 @@ impl State {
     }
 
     fn down(&mut self) {
-        let vscroll = self.term_size.1 / 5;
+        let vscroll = self.size.1 / 5;
         if self.cursor.1 >= vscroll * 4
-            && self.scroll.1 + (self.term_size.1 as usize - 1) < self.buf.len()
+            && self.scroll.1 + (self.size.1 as usize).saturating_sub(1) < self.rows()
         {
             self.scroll.1 += 1;
         } else {
-            self.cursor.1 = (self.cursor.1 + 1).min(self.term_size.1 - 2);
+            self.cursor.1 = (self.cursor.1 + 1).min(self.size.1.saturating_sub(2));
         }
     }
 
     pub fn event(&mut self, c: char) {
+        self.message = None;
+
         let action = match &mut self.mode {
             Mode::Normal => match c {
                 'q' | '\x03' => Action::Exit,
@@ -154,6 +704,18 @@ impl State {
                     self.mode = Mode::Goto(String::new());
                     Action::Cont
                 }
+                '&' => {
+                    self.mode = Mode::Filter(String::new());
+                    Action::Cont
+                }
+                't' => Action::Table,
+                'S' => Action::Sort,
+                'v' => Action::Select(Select::Char),
+                'V' => Action::Select(Select::Line),
+                'y' if self.rows() == 0 => Action::Cont,
+                'y' => Action::Yank,
+                'Y' => Action::YankMatch,
+                'E' => Action::Errors,
                 _ => Action::Cont,
             },
             Mode::Esc => match c {
@@ -172,28 +734,28 @@ impl State {
                     Action::Cont
                 }
                 'a' => {
-                    if num.len() > 0 {
+                    if !num.is_empty() {
                         Action::Up(num.parse().unwrap())
                     } else {
                         Action::Up(1)
                     }
                 }
                 'b' => {
-                    if num.len() > 0 {
+                    if !num.is_empty() {
                         Action::Down(num.parse().unwrap())
                     } else {
                         Action::Down(1)
                     }
                 }
                 'c' => {
-                    if num.len() > 0 {
+                    if !num.is_empty() {
                         Action::Right(num.parse().unwrap())
                     } else {
                         Action::Right(1)
                     }
                 }
                 'd' => {
-                    if num.len() > 0 {
+                    if !num.is_empty() {
                         Action::Left(num.parse().unwrap())
                     } else {
                         Action::Left(1)
@@ -201,9 +763,9 @@ impl State {
                 }
                 '~' => {
                     if num == "5" {
-                        Action::Up(self.term_size.1 as usize - 1)
+                        Action::Up((self.size.1 as usize).saturating_sub(1))
                     } else if num == "6" {
-                        Action::Down(self.term_size.1 as usize - 1)
+                        Action::Down((self.size.1 as usize).saturating_sub(1))
                     } else {
                         Action::Cont
                     }
@@ -219,39 +781,80 @@ impl State {
                     Action::Cont
                 }
                 'g' => {
-                    let n = if num.len() == 0 {
+                    let n = if num.is_empty() {
                         0_usize
                     } else {
                         num.parse().unwrap()
                     }
                     .saturating_sub(1);
 
-                    Action::Jump(n, 0)
+                    // line numbers stay the same when filtering
+                    Action::Jump(self.row_of(n), 0)
                 }
-                'e' => Action::Jump(self.buf.len(), 0),
+                'e' => Action::Jump(self.rows(), 0),
                 'h' => Action::Jump(self.cursor.1 as usize + self.scroll.1, 0),
                 'l' => {
-                    let i = self.cursor.1 as usize + self.scroll.1;
-                    let line = &self.buf[i];
-                    Action::Jump(i, line.len())
+                    let i =
+                        (self.cursor.1 as usize + self.scroll.1).min(self.rows().saturating_sub(1));
+                    // there's no line to go along when nothing is shown
+                    if i >= self.rows() {
+                        Action::Jump(0, 0)
+                    } else {
+                        Action::Jump(i, self.display(self.line_of(i)).text.len())
+                    }
                 }
+                'H' => Action::Header(
+                    match num.parse() {
+                        Ok(n) => n,
+                        Err(_) if self.header.0 == 0 => 1,
+                        Err(_) => 0,
+                    },
+                    self.header.1,
+                ),
+                'C' => Action::Header(
+                    self.header.0,
+                    match num.parse() {
+                        Ok(n) => n,
+                        Err(_) if self.header.1 == 0 => 1,
+                        Err(_) => 0,
+                    },
+                ),
                 's' => {
-                    let i = (self.cursor.1 as usize + self.scroll.1)
-                        .min(self.buf.len().saturating_sub(1));
-                    let n = self.buf[i]
-                        .chars()
-                        .enumerate()
-                        .skip_while(|(_, c)| c.is_whitespace())
-                        .next()
-                        .unwrap_or((0, '?'))
-                        .0;
-                    Action::Jump(i, n)
+                    let i =
+                        (self.cursor.1 as usize + self.scroll.1).min(self.rows().saturating_sub(1));
+                    if i >= self.rows() {
+                        Action::Jump(0, 0)
+                    } else {
+                        let n = self
+                            .display(self.line_of(i))
+                            .text
+                            .chars()
+                            .position(|c| !c.is_whitespace())
+                            .unwrap_or(0);
+                        Action::Jump(i, n)
+                    }
                 }
                 _ => {
                     self.mode = Mode::Normal;
                     Action::Cont
                 }
             },
+            Mode::Filter(pattern) => match c {
+                '\r' | '\n' => Action::Filter(std::mem::take(pattern)),
+                '\x1b' | '\x03' => {
+                    self.mode = Mode::Normal;
+                    Action::Cont
+                }
+                '\x7f' | '\x08' => {
+                    pattern.pop();
+                    Action::Cont
+                }
+                _ if !c.is_control() => {
+                    pattern.push(c);
+                    Action::Cont
+                }
+                _ => Action::Cont,
+            },
         };
 
         match action {
@@ -269,6 +872,32 @@ impl State {
                     self.up()
                 }
             }
+            // tables move a cell at a time
+            Action::Left(n) if self.table.is_some() => {
+                let offsets = self.table.as_ref().unwrap().offsets();
+                let col = self.scroll.0 + self.cursor.0 as usize;
+                let j = offsets.iter().rposition(|o| *o < col).unwrap_or(0);
+                let row = self.scroll.1 + self.cursor.1 as usize;
+                self.jump(
+                    row,
+                    offsets
+                        .get(j.saturating_sub(n.max(1) - 1))
+                        .copied()
+                        .unwrap_or(0),
+                );
+            }
+            Action::Right(n) if self.table.is_some() => {
+                let offsets = self.table.as_ref().unwrap().offsets();
+                let col = self.scroll.0 + self.cursor.0 as usize;
+                let j = offsets
+                    .iter()
+                    .position(|o| *o > col)
+                    .unwrap_or(offsets.len());
+                let row = self.scroll.1 + self.cursor.1 as usize;
+                if let Some(o) = offsets.get(j + n.max(1) - 1).or(offsets.last()) {
+                    self.jump(row, *o);
+                }
+            }
             Action::Left(n) => {
                 for _ in 0..n {
                     self.left()
@@ -279,23 +908,113 @@ impl State {
                     self.right()
                 }
             }
-            Action::Jump(row, col) => {
-                let row = row.min(self.buf.len() - 1);
-
-                while self.scroll.1 + (self.cursor.1 as usize) < row {
-                    self.down();
+            Action::Jump(row, col) => self.jump(row, col),
+            // an empty pattern clears the filters
+            Action::Filter(ref pattern) if pattern.is_empty() => {
+                self.filters.clear();
+                self.refilter();
+            }
+            Action::Filter(ref pattern) => match Filter::parse(pattern) {
+                Ok(f) => {
+                    self.filters.push(f);
+                    self.refilter();
                 }
+                Err(e) => self.message = Some(e.to_string()),
+            },
+            Action::Header(rows, cols) => {
+                self.header = (rows, cols);
+                self.refilter();
+            }
+            Action::Table => {
+                self.table = match self.table {
+                    Some(_) => None,
+                    None => Some(Table::new(Format::Auto)),
+                };
+                self.refilter();
+                self.jump(self.scroll.1 + self.cursor.1 as usize, 0);
+            }
+            // pressing the same selection key again stops selecting
+            Action::Select(kind) => {
+                let pos = (
+                    self.scroll.1 + self.cursor.1 as usize,
+                    self.scroll.0 + self.cursor.0 as usize,
+                );
+                self.select = match self.select {
+                    Some((k, _, _)) if k == kind => None,
+                    Some((_, row, col)) => Some((kind, row, col)),
+                    None => Some((kind, pos.0, pos.1)),
+                };
+            }
+            // copy the selection, or the current line without one
+            Action::Yank => {
+                let text = match self.select {
+                    Some((_, row, _)) => {
+                        let end = self.scroll.1 + self.cursor.1 as usize;
+                        (row.min(end)..=row.max(end).min(self.rows().saturating_sub(1)))
+                            .map(|r| {
+                                let range = self.selected(r);
+                                self.display(self.line_of(r))
+                                    .text
+                                    .graphemes(true)
+                                    .enumerate()
+                                    .filter(|(i, _)| range.contains(i))
+                                    .map(|(_, g)| g)
+                                    .collect::<String>()
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    }
+                    None if self.rows() > 0 => {
+                        let r = (self.scroll.1 + self.cursor.1 as usize).min(self.rows() - 1);
+                        self.display(self.line_of(r)).text
+                    }
+                    None => String::new(),
+                };
 
-                while self.scroll.1 + (self.cursor.1 as usize) > row {
-                    self.up();
-                }
+                clipboard::copy(&text);
+                self.message = Some(format!("yanked {} lines", text.lines().count()));
+                self.select = None;
+            }
+            // copy what the last filter matched on the current line
+            Action::YankMatch => {
+                let r = self.scroll.1 + self.cursor.1 as usize;
+                let found = self
+                    .filters
+                    .iter()
+                    .rev()
+                    .find(|f| !f.invert)
+                    .filter(|_| r < self.rows())
+                    .and_then(|f| {
+                        f.re.find(&self.display(self.line_of(r)).text)
+                            .map(|m| m.as_str().to_string())
+                    });
 
-                while self.scroll.0 + (self.cursor.0 as usize) < col {
-                    self.right();
+                match found {
+                    Some(m) => {
+                        clipboard::copy(&m);
+                        self.message = Some(format!("yanked '{m}'"));
+                    }
+                    None => self.message = Some("no match on this line".into()),
                 }
-
-                while self.scroll.0 + (self.cursor.0 as usize) > col {
-                    self.left();
+            }
+            // sort by the column under the cursor, going from ascending to
+            // descending to unsorted
+            Action::Sort => {
+                if let Some(t) = self.table.as_mut() {
+                    let col = t.column_at(self.scroll.0 + self.cursor.0 as usize);
+                    t.sort = match t.sort {
+                        Some((c, false)) if c == col => Some((col, true)),
+                        Some((c, true)) if c == col => None,
+                        _ => Some((col, false)),
+                    };
+                    self.refilter();
+                }
+            }
+            Action::Errors => {
+                if self.showing_errors || !self.errors.is_empty() {
+                    self.toggle_errors();
+                } else {
+                    self.message = Some("nothing was written to stderr".into());
                 }
             }
             Action::Cont => {}
@@ -306,92 +1025,299 @@ impl State {
         }
     }
 
+    // switch between the output and what the last run wrote to stderr
+    fn toggle_errors(&mut self) {
+        std::mem::swap(&mut self.buf, &mut self.errors);
+        self.showing_errors = !self.showing_errors;
+
+        let at = std::mem::replace(&mut self.behind, (self.scroll, self.cursor));
+        (self.scroll, self.cursor) = at;
+        self.select = None;
+        self.refilter();
+    }
+
+    fn jump(&mut self, row: usize, col: usize) {
+        let row = row.min(self.rows().saturating_sub(1));
+
+        while self.scroll.1 + (self.cursor.1 as usize) < row {
+            self.down();
+        }
+
+        while self.scroll.1 + (self.cursor.1 as usize) > row {
+            self.up();
+        }
+
+        while self.scroll.0 + (self.cursor.0 as usize) < col {
+            self.right();
+        }
+
+        while self.scroll.0 + (self.cursor.0 as usize) > col {
+            self.left();
+        }
+    }
+
+    // whether a filter is being typed, which takes every key
+    pub fn typing(&self) -> bool {
+        matches!(self.mode, Mode::Filter(_))
+    }
+
+    // the file or command shown in the pane
+    pub fn title(&self) -> String {
+        let title = if let Some(f) = self.opts.file.as_ref() {
+            f.to_string_lossy().into_owned()
+        } else {
+            self.cmd
+                .get_args()
+                .map(|arg| arg.to_string_lossy())
+                .nth(1)
+                .unwrap()
+                .into_owned()
+        };
+
+        match self.opts.pipe.as_ref() {
+            Some(p) => format!("{title} | {p}"),
+            None => title,
+        }
+    }
+
     pub fn draw(&mut self) {
-        self.term_size = terminal::size().unwrap();
         let mut stdout = std::io::stdout();
 
-        let num_digs = if self.buf.len() == 0 {
+        let num_digs = if self.buf.is_empty() {
             0
         } else {
             self.buf.len().ilog10()
         } + 1;
+        let width = (self.size.0 as usize).saturating_sub(num_digs as usize + 2);
 
-        let start_row = self.scroll.1;
-        let end_row = (start_row + self.term_size.1 as usize - 1).min(self.buf.len());
+        stdout.queue(terminal::DisableLineWrap).unwrap();
 
-        stdout
-            .queue(terminal::Clear(terminal::ClearType::All))
-            .unwrap()
-            .queue(terminal::DisableLineWrap)
-            .unwrap()
-            .queue(cursor::MoveTo(0, 0))
-            .unwrap();
+        // other panes may be next to us, so every row is padded out to our
+        // width instead of clearing the screen
+        for row in 0..self.size.1.saturating_sub(1) {
+            stdout
+                .queue(cursor::MoveTo(self.origin.0, self.origin.1 + row))
+                .unwrap();
 
-        for (i, line) in self.buf[start_row..end_row].iter().enumerate() {
-            if i != 0 {
-                stdout.queue(cursor::MoveDown(1)).unwrap();
+            // the header covers the rows that have scrolled under it
+            let r = if (row as usize) < self.header.0 {
+                row as usize
+            } else {
+                self.scroll.1 + row as usize
+            };
+
+            if r >= self.rows() {
+                print!("{:1$}", "", self.size.0 as usize);
+                continue;
             }
+            let i = self.line_of(r);
 
-            stdout.queue(cursor::MoveToColumn(0)).unwrap();
             stdout
                 .queue(style::SetForegroundColor(style::Color::DarkGrey))
                 .unwrap();
 
-            print!("{:>width$}│ ", start_row + i + 1, width = num_digs as usize);
+            print!("{:>width$}│ ", i + 1, width = num_digs as usize);
             stdout.queue(style::ResetColor).unwrap();
 
-            UnicodeSegmentation::graphemes(line.as_str(), true)
-                .skip(self.scroll.0)
-                .take(self.term_size.0 as usize - num_digs as usize - 2)
-                .for_each(|s| print!("{s}"));
+            let line = self.display(i);
+            let n = draw_line(
+                &mut stdout,
+                &line,
+                self.selected(r),
+                self.header.1,
+                self.scroll.0,
+                width,
+            );
+            print!("{:1$}", "", width - n);
         }
 
         // draw bottom bar
         stdout
-            .queue(cursor::MoveTo(0, self.term_size.1 - 1))
+            .queue(cursor::MoveTo(
+                self.origin.0,
+                (self.origin.1 + self.size.1).saturating_sub(1),
+            ))
             .unwrap()
-            .queue(style::SetForegroundColor(style::Color::Magenta))
-            .unwrap();
-
-        print!(
-            "{} {}",
-            self.mode.to_string(),
-            if let Some(f) = self.opts.file.as_ref() {
-                f.to_string_lossy()
+            .queue(style::SetForegroundColor(if self.focused {
+                style::Color::Magenta
             } else {
-                self.cmd
-                    .get_args()
-                    .map(|arg| arg.to_string_lossy())
-                    .nth(1)
-                    .unwrap()
-            },
-        );
+                style::Color::DarkGrey
+            }))
+            .unwrap();
 
         let cur_s = format!(
             "{}:{}",
             self.cursor.1 as usize + self.scroll.1 + 1,
             self.cursor.0 as usize + self.scroll.0 + 1
         );
-        stdout
-            .queue(cursor::MoveToColumn(self.term_size.0 - cur_s.len() as u16))
-            .unwrap();
-        print!("{}", cur_s);
 
-        stdout
-            .queue(cursor::MoveTo(
-                num_digs as u16 + 2 + self.cursor.0 as u16,
-                self.cursor.1 as u16,
-            ))
-            .unwrap();
+        let mode = match (&self.mode, self.select) {
+            (Mode::Normal, Some((Select::Char, _, _))) => "VIS ".to_string(),
+            (Mode::Normal, Some((Select::Line, _, _))) => "VLN ".to_string(),
+            (mode, _) => mode.to_string(),
+        };
+
+        let bar = if let Some(m) = self.message.as_ref() {
+            format!("{} {}", mode, m)
+        } else if self.showing_errors {
+            format!("{} {} [stderr]", mode, self.title())
+        } else if let (Some(f), true) = (self.failed.as_ref(), self.kept) {
+            format!("{} {} [{f}, showing the last output]", mode, self.title())
+        } else if let Some(f) = self.failed.as_ref() {
+            format!("{} {} [{f}]", mode, self.title())
+        } else if !self.filters.is_empty() {
+            format!("{} {} [&{}]", mode, self.title(), self.filters.len())
+        } else {
+            format!("{} {}", mode, self.title())
+        };
+        let bar_width = (self.size.0 as usize).saturating_sub(cur_s.len() + 1);
+        let bar = UnicodeSegmentation::graphemes(bar.as_str(), true)
+            .take(bar_width)
+            .collect::<String>();
+        print!("{bar:bar_width$} {cur_s}");
+        stdout.queue(style::ResetColor).unwrap();
+
+        if self.focused {
+            stdout
+                .queue(cursor::MoveTo(
+                    self.origin.0 + num_digs as u16 + 2 + self.cursor.0,
+                    self.origin.1 + self.cursor.1,
+                ))
+                .unwrap();
+        }
         stdout.flush().unwrap();
     }
 
     pub fn exit(&mut self) -> ! {
-        terminal::disable_raw_mode().unwrap();
-        std::io::stdout()
-            .execute(terminal::LeaveAlternateScreen)
-            .unwrap();
+        // quitting before an until condition is met counts as failing
+        let code = if self.opts.until.is_some() { 1 } else { 0 };
+        self.leave(code)
+    }
+
+    // an until condition was met, let the user know and exit
+    fn done(&mut self, reason: &str) -> ! {
+        self.alert(reason);
+        self.leave(0)
+    }
+
+    // get the attention of the user in the ways that were asked for
+    fn alert(&self, reason: &str) {
+        if self.opts.bell {
+            notify::bell();
+        }
+        if let Some(kind) = self.opts.notify {
+            notify::notify(kind, &format!("mp: {}", self.title()), reason);
+        }
+        if self.opts.set_title {
+            notify::title(&format!("mp: {}: {reason}", self.title()));
+        }
+    }
+
+    pub fn leave(&mut self, code: i32) -> ! {
+        process::kill_all();
+
+        if !self.opts.batch {
+            terminal::disable_raw_mode().unwrap();
+            std::io::stdout()
+                .execute(terminal::LeaveAlternateScreen)
+                .unwrap();
+        }
 
-        std::process::exit(0);
+        std::process::exit(code);
     }
 }
+
+// build the command that produces the output for a pane
+fn command(opts: &Options) -> Command {
+    if let Some(f) = opts.file.as_ref() {
+        shell(opts, &format!("cat {}", f.to_string_lossy()))
+    } else {
+        shell(opts, opts.cmd.as_deref().unwrap_or_default())
+    }
+}
+
+// write the lines of a buffer, either as plain text or exactly as they were
+// read with any colours left in
+fn write_lines(buf: &Buffer, f: &mut impl Write, raw: bool) -> std::io::Result<usize> {
+    for i in 0..buf.len() {
+        if raw {
+            f.write_all(buf.line(i))?;
+        } else {
+            let text = line::normalize(&line::strip_ansi(buf.line(i))).text;
+            f.write_all(text.as_bytes())?;
+        }
+        f.write_all(b"\n")?;
+    }
+
+    Ok(buf.len())
+}
+
+// run a script with sh, in the directory and environment asked for
+fn shell(opts: &Options, script: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(script);
+
+    if let Some(dir) = opts.cwd.as_ref() {
+        cmd.current_dir(dir);
+    }
+    cmd.envs(opts.env.iter().map(|(k, v)| (k, v)));
+
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    cmd
+}
+
+// print the visible graphemes of a line, switching attributes as the spans
+// change. returns how many were printed
+fn draw_line(
+    stdout: &mut std::io::Stdout,
+    line: &Line,
+    selected: Range<usize>,
+    frozen: usize,
+    skip: usize,
+    width: usize,
+) -> usize {
+    let mut curr = (Attr::default(), false);
+    let mut n = 0;
+    // the frozen columns stay put and the rest scroll past them
+    for (j, (i, g)) in UnicodeSegmentation::grapheme_indices(line.text.as_str(), true)
+        .enumerate()
+        .filter(|(j, _)| *j < frozen || *j >= frozen + skip)
+        .take(width)
+    {
+        let (attr, sel) = (line.attr_at(i), selected.contains(&j));
+        if (attr, sel) != curr {
+            stdout
+                .queue(style::SetAttribute(style::Attribute::Reset))
+                .unwrap();
+            if attr.bold {
+                stdout
+                    .queue(style::SetAttribute(style::Attribute::Bold))
+                    .unwrap();
+            }
+            if attr.underline {
+                stdout
+                    .queue(style::SetAttribute(style::Attribute::Underlined))
+                    .unwrap();
+            }
+            // the selection is shown by flipping escapes back
+            if attr.escape != sel {
+                stdout
+                    .queue(style::SetAttribute(style::Attribute::Reverse))
+                    .unwrap();
+            }
+            curr = (attr, sel);
+        }
+        print!("{g}");
+        n += 1;
+    }
+
+    if curr != (Attr::default(), false) {
+        stdout
+            .queue(style::SetAttribute(style::Attribute::Reset))
+            .unwrap();
+    }
+
+    n
+}
@@ -0,0 +1,39 @@
+// waiting for the output to reach some state, so that eg.
+// `mp --until=Running -t2 -- kubectl get pod x` exits once the pod is up
+
+use std::process::ExitStatus;
+
+use regex::Regex;
+
+use crate::buffer::Buffer;
+use crate::line;
+
+#[derive(Clone)]
+pub enum Until {
+    // some line matches
+    Match(Regex),
+    // no line matches any more
+    NoMatch(Regex),
+    // the command exited with this code
+    Exit(i32),
+    // the output is different to the first time it was read
+    Change,
+}
+
+impl Until {
+    // whether the condition has been met, and a description of why
+    pub fn met(&self, buf: &Buffer, status: Option<ExitStatus>, changed: bool) -> Option<String> {
+        let matched =
+            |re: &Regex| (0..buf.len()).any(|i| re.is_match(&line::normalize(buf.line(i)).text));
+
+        match self {
+            Until::Match(re) if matched(re) => Some(format!("output matched '{re}'")),
+            Until::NoMatch(re) if !matched(re) => Some(format!("output no longer matches '{re}'")),
+            Until::Exit(c) if status.and_then(|s| s.code()) == Some(*c) => {
+                Some(format!("command exited with {c}"))
+            }
+            Until::Change if changed => Some("output changed".into()),
+            _ => None,
+        }
+    }
+}
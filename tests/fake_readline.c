@@ -0,0 +1,61 @@
+// a stand in for libreadline that reads lines from stdin, so the preload
+// shim can be loaded in front of it by readline_host. lines added to and
+// removed from the history are printed
+
+#define _GNU_SOURCE
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+char *readline(const char *prompt) {
+    (void)prompt;
+
+    char *line = NULL;
+    size_t cap = 0;
+    ssize_t n = getline(&line, &cap, stdin);
+    if (n == -1) {
+        free(line);
+        return NULL;
+    }
+
+    if (n > 0 && line[n-1] == '\n')
+        line[n-1] = 0;
+    return line;
+}
+
+typedef struct { char *line; } HIST_ENTRY;
+
+HIST_ENTRY *entries[65];
+int history_length = 0;
+
+void add_history(const char *line) {
+    printf("history: %s\n", line);
+    if (history_length == 64)
+        return;
+
+    HIST_ENTRY *e = malloc(sizeof(HIST_ENTRY));
+    e->line = strdup(line);
+    entries[history_length++] = e;
+    entries[history_length] = NULL;
+}
+
+HIST_ENTRY **history_list(void) {
+    return entries;
+}
+
+HIST_ENTRY *remove_history(int which) {
+    if (which < 0 || which >= history_length)
+        return NULL;
+
+    HIST_ENTRY *e = entries[which];
+    printf("removed: %s\n", e->line);
+    memmove(entries+which, entries+which+1, (history_length-which) * sizeof(HIST_ENTRY *));
+    history_length--;
+    return e;
+}
+
+void *free_history_entry(HIST_ENTRY *e) {
+    free(e->line);
+    free(e);
+    return NULL;
+}
@@ -0,0 +1,41 @@
+#!/bin/sh
+# run the preload shim inside readline_host, checking when it rewrites lines
+#   tests/preload_host.sh target
+
+dir=$1
+failed=0
+passed=0
+
+# expect <environment> <input> <output>
+expect() {
+    got=$(printf "$2" | env $1 LD_PRELOAD="$dir/preload_test.so" "$dir/readline_host")
+    if [ "$got" != "$(printf "$3")" ]; then
+        echo "FAIL: $1 $2"
+        echo "  want: $3"
+        echo "  got:  $got"
+        failed=$((failed + 1))
+    else
+        passed=$((passed + 1))
+    fi
+}
+
+# the host isn't in /etc/shells, so it is left alone unless forced
+expect "" 'ls | mp\n' 'ls | mp'
+expect "MP_PRELOAD_FORCE=1" 'ls | mp\n' "history: ls | mp\n mp -- 'ls'"
+expect "MP_PRELOAD_FORCE=1" 'ls | mp || true\n' 'ls | mp || true'
+expect "MP_PRELOAD_FORCE=0" 'ls | mp\n' 'ls | mp'
+expect "MP_PRELOAD_FORCE=yes" 'ls | mp\n' 'ls | mp'
+
+# only the rewritten line when the history is turned off
+expect "MP_PRELOAD_FORCE=1 MP_PRELOAD_HISTORY=0" 'ls | mp\n' "mp -- 'ls'"
+
+# lines keep coming after one is rewritten, and end of input gets through
+expect "MP_PRELOAD_FORCE=1" 'ls | mp\necho hi\n' "history: ls | mp\n mp -- 'ls'\necho hi"
+
+# a shell that adds every line has the rewritten one taken back out when the
+# next line is read, while lines it didn't rewrite stay
+expect "MP_PRELOAD_FORCE=1 HOST_HISTORY=1" 'ls | mp\necho hi\n' "history: ls | mp\nhistory:  mp -- 'ls'\n mp -- 'ls'\nremoved:  mp -- 'ls'\nhistory: echo hi\necho hi"
+expect "MP_PRELOAD_FORCE=1 HOST_HISTORY=1" 'echo hi\nls\n' "history: echo hi\necho hi\nhistory: ls\nls"
+
+echo "host: $passed passed, $failed failed"
+[ $failed -eq 0 ]
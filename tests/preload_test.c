@@ -0,0 +1,183 @@
+// tests for the command line rewriting in the preload shim, run with
+// `make check`
+
+#include "../src/preload.c"
+
+#include <fcntl.h>
+#include <sys/stat.h>
+
+struct test {
+    const char *input;
+    // NULL when the line should be left alone
+    const char *want;
+};
+
+struct test tests[] = {
+    // the basics
+    { "ls | mp", "mp -- 'ls'" },
+    { "cat /etc/passwd | grep root | mp -t5", "mp -t5 -- 'cat /etc/passwd | grep root'" },
+    { "ls\t|\tmp\t-t1\t", "mp\t-t1 -- 'ls'" },
+    { "ls", NULL },
+    { "mp -t1 -- ls", NULL },
+    { "| mp", NULL },
+
+    // only the mp command itself
+    { "grep mpx | mpx", NULL },
+    { "ls | mp2", NULL },
+    { "ls | mpv -", NULL },
+    { "grep mpx file | mp -t1", "mp -t1 -- 'grep mpx file'" },
+
+    // commands after mp
+    { "ls | mp | cat", "mp --pipe 'cat' -- 'ls'" },
+    { "ls|mp|cat", "mp --pipe 'cat' -- 'ls'" },
+    { "ls | grep x | mp -t2 | tail -3", "mp -t2 --pipe 'tail -3' -- 'ls | grep x'" },
+    { "ps aux | mp -t1 | grep ssh | sort", "mp -t1 --pipe 'grep ssh | sort' -- 'ps aux'" },
+    { "ls | mp | grep \"'\"", "mp --pipe 'grep \"'\\''\"' -- 'ls'" },
+    { "cd /tmp && ls | mp | wc", "cd /tmp && mp --pipe 'wc' -- 'ls'" },
+    { "ls | mp |", NULL },
+    { "mp | cat", NULL },
+    { "ls | mpx | cat", NULL },
+    { "ls | mp | cat; echo", NULL },
+
+    // lists, where only the last pipeline counts
+    { "cat a | mp -t1 || echo x", NULL },
+    { "cat a | mp -t1 && echo x", NULL },
+    { "ls | mp &", NULL },
+    { "cd /tmp && ls | mp -t2", "cd /tmp && mp -t2 -- 'ls'" },
+    { "false || ls | mp", "false || mp -- 'ls'" },
+    { "ls; ps aux | mp", "ls; mp -- 'ps aux'" },
+    { "sleep 1 & ls | mp", "sleep 1 & mp -- 'ls'" },
+
+    // quoting
+    { "echo '|' | mp", "mp -- 'echo '\\''|'\\'''" },
+    { "echo '| mp'", NULL },
+    { "echo \"a | mp\"", NULL },
+    { "echo a \\| mp", NULL },
+    { "echo \"a\\\" | mp\"", NULL },
+    { "echo $'it\\'s | mp'", NULL },
+    { "echo $'it\\'s' | mp", "mp -- 'echo $'\\''it\\'\\''s'\\'''" },
+    { "echo 'unclosed | mp", NULL },
+    { "echo \"unclosed | mp", NULL },
+    { "echo a \\", NULL },
+
+    // subshells and substitutions
+    { "(ls | mp)", NULL },
+    { "(cd /tmp; ls) | mp", "mp -- '(cd /tmp; ls)'" },
+    { "echo $(ls | mp)", NULL },
+    { "echo $(ls | wc -l) | mp", "mp -- 'echo $(ls | wc -l)'" },
+    { "echo \"$(echo \")\" | wc)\" | mp", "mp -- 'echo \"$(echo \")\" | wc)\"'" },
+    { "echo `ls | mp`", NULL },
+    { "echo `ls | wc` | mp", "mp -- 'echo `ls | wc`'" },
+    { "diff <(ls a) <(ls b) | mp", "mp -- 'diff <(ls a) <(ls b)'" },
+    { "echo ${x:-a|b} | mp", "mp -- 'echo ${x:-a|b}'" },
+    { "{ ls; ps; } | mp", "mp -- '{ ls; ps; }'" },
+    { "echo $((1 | 2)) | mp", "mp -- 'echo $((1 | 2))'" },
+
+    // redirections and here strings
+    { "cat <<< 'a|b' | mp", "mp -- 'cat <<< '\\''a|b'\\'''" },
+    { "cat <<< a|mp", "mp -- 'cat <<< a'" },
+    { "ls 2>&1 | mp", "mp -- 'ls 2>&1'" },
+    { "ls &> /dev/null | mp", "mp -- 'ls &> /dev/null'" },
+    { "echo a >| f", NULL },
+    { "ls |& mp", NULL },
+
+    // comments
+    { "ls | mp # watch it", NULL },
+    { "echo a#b | mp", "mp -- 'echo a#b'" },
+};
+
+// write a shells file to a temporary path, which the caller removes
+char *shells(const char *contents) {
+    static char path[32];
+    strcpy(path, "/tmp/mp_shells_XXXXXX");
+    int fd = mkstemp(path);
+    write(fd, contents, strlen(contents));
+    close(fd);
+    return path;
+}
+
+struct shell_test {
+    const char *contents;
+    const char *path;
+    bool want;
+};
+
+struct shell_test shell_tests[] = {
+    { "/bin/sh\n/bin/bash\n", "/bin/bash", true },
+    { "/bin/sh\n/bin/bash", "/bin/bash", true },
+    { "/bin/sh\r\n/bin/bash\r\n", "/bin/bash", true },
+    { "# /bin/bash\n\n  \n", "/bin/bash", false },
+    { "  /bin/bash  \n", "/bin/bash", true },
+    { "/bin/bas\n/bin/bashx\n", "/bin/bash", false },
+    { "/bin/bash\n", "/bin/bas", false },
+    { "", "/bin/bash", false },
+    // the path of a running shell has its symlinks resolved
+    { "/tmp/mp_shells_link/sh\n", "/tmp/mp_shells_dir/sh", true },
+};
+
+int check_shells() {
+    int failed = 0;
+    int n = sizeof(shell_tests) / sizeof(shell_tests[0]);
+
+    mkdir("/tmp/mp_shells_dir", 0700);
+    close(open("/tmp/mp_shells_dir/sh", O_CREAT | O_WRONLY, 0700));
+    symlink("/tmp/mp_shells_dir", "/tmp/mp_shells_link");
+
+    for (int i=0; i<n; i++) {
+        struct shell_test *t = &shell_tests[i];
+        char *path = shells(t->contents);
+        if (check_shell(t->path, path) != t->want) {
+            printf("FAIL: %s in %s\n", t->path, t->contents);
+            failed++;
+        }
+        unlink(path);
+    }
+
+    // a missing file, or a line longer than any fixed buffer
+    if (check_shell("/bin/bash", "/nonexistent/shells")) {
+        printf("FAIL: missing shells file\n");
+        failed++;
+    }
+
+    char long_line[4096];
+    memset(long_line, 'a', sizeof(long_line) - 2);
+    long_line[0] = '/';
+    long_line[sizeof(long_line) - 2] = '\n';
+    long_line[sizeof(long_line) - 1] = 0;
+    char *path = shells(long_line);
+    long_line[sizeof(long_line) - 2] = 0;
+    if (!check_shell(long_line, path)) {
+        printf("FAIL: long line in shells file\n");
+        failed++;
+    }
+    unlink(path);
+
+    unlink("/tmp/mp_shells_link");
+    unlink("/tmp/mp_shells_dir/sh");
+    rmdir("/tmp/mp_shells_dir");
+
+    printf("shells: %d passed, %d failed\n", n + 2 - failed, failed);
+    return failed;
+}
+
+int main() {
+    int failed = 0;
+    int n = sizeof(tests) / sizeof(tests[0]);
+
+    for (int i=0; i<n; i++) {
+        struct test *t = &tests[i];
+        const char *want = t->want ? t->want : t->input;
+
+        char *got = rewrite(strdup(t->input));
+        if (strcmp(got, want) != 0) {
+            printf("FAIL: %s\n  want: %s\n  got:  %s\n", t->input, want, got);
+            failed++;
+        }
+        free(got);
+    }
+
+    printf("rewrite: %d passed, %d failed\n", n - failed, failed);
+
+    failed += check_shells();
+    return failed != 0;
+}
@@ -0,0 +1,23 @@
+// a program that reads lines like a shell would, through readline from
+// libfake_readline.so, and prints what it got back. with HOST_HISTORY=1
+// every line goes in the history, like bash without HISTCONTROL=ignorespace
+//   LD_PRELOAD=magic_pager.so readline_host < lines
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+char *readline(const char *prompt);
+void add_history(const char *line);
+
+int main() {
+    const char *history = getenv("HOST_HISTORY");
+    char *line;
+    while ((line = readline("$ "))) {
+        if (history && strcmp(history, "1") == 0)
+            add_history(line);
+        printf("%s\n", line);
+        free(line);
+    }
+    return 0;
+}